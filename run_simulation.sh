@@ -6,4 +6,4 @@
 #SBATCH -t 1-00:00:00
 #SBATCH --mem=128G
 
-cargo run --release
\ No newline at end of file
+cargo run --release -- run --config config.toml
\ No newline at end of file
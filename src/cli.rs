@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(about = "Agent based simulation of the evolution of aging under the GLA mortality model")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run every replicate of a single configuration
+    Run {
+        #[command(flatten)]
+        config: ConfigArgs,
+        /// Directory for the results, overrides output_directory
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Continue an interrupted run from its results file and checkpoints
+        #[arg(long)]
+        resume: bool,
+    },
+    /// Run the configuration once for every point of a parameter grid
+    Sweep {
+        #[command(flatten)]
+        config: ConfigArgs,
+        /// Directory for the results, overrides output_directory
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Grid axis as key=value1,value2,... (repeat for a cartesian product)
+        #[arg(long = "grid", value_name = "KEY=VALUES", required = true)]
+        grid: Vec<String>,
+        /// Continue interrupted grid points from their results files and checkpoints
+        #[arg(long)]
+        resume: bool,
+    },
+    /// Print the resolved parameters without running anything
+    Inspect {
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+}
+
+#[derive(Args)]
+pub struct ConfigArgs {
+    /// Path to the TOML configuration file
+    #[arg(long, default_value = "config.toml")]
+    pub config: PathBuf,
+    /// Override a single configuration key, as key=value in TOML syntax
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub set: Vec<String>,
+}
+
+/// Parses `key=value` where the value is TOML, falling back to a bare string.
+pub fn parse_override(assignment: &str) -> Result<(String, toml::Value), String> {
+    let (key, value) = assignment
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{}'", assignment))?;
+    Ok((key.trim().to_string(), parse_value(value.trim())))
+}
+
+/// Parses `key=v1,v2,...`, splitting only on commas outside of brackets so
+/// that array values such as `[0.1, 0.0]` stay whole.
+pub fn parse_grid_axis(axis: &str) -> Result<(String, Vec<toml::Value>), String> {
+    let (key, values) = axis
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUES, got '{}'", axis))?;
+
+    let mut parsed_values = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, character) in values.char_indices() {
+        match character {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ',' if depth == 0 => {
+                parsed_values.push(parse_value(values[start..i].trim()));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parsed_values.push(parse_value(values[start..].trim()));
+
+    if parsed_values.iter().any(|v| v.as_str() == Some("")) {
+        return Err(format!("empty value in grid axis '{}'", axis));
+    }
+    Ok((key.trim().to_string(), parsed_values))
+}
+
+fn parse_value(value: &str) -> toml::Value {
+    match toml::from_str::<toml::Table>(&format!("value = {}", value)) {
+        Ok(mut table) => table.remove("value").unwrap(),
+        Err(_) => toml::Value::String(value.to_string()),
+    }
+}
+
+/// Cartesian product of the grid axes, one list of overrides per grid point.
+pub fn grid_points(axes: &[(String, Vec<toml::Value>)]) -> Vec<Vec<(String, toml::Value)>> {
+    let mut points: Vec<Vec<(String, toml::Value)>> = vec![Vec::new()];
+    for (key, values) in axes {
+        points = points
+            .iter()
+            .flat_map(|point| {
+                values.iter().map(move |value| {
+                    let mut extended = point.clone();
+                    extended.push((key.clone(), value.clone()));
+                    extended
+                })
+            })
+            .collect();
+    }
+    points
+}
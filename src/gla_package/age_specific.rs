@@ -0,0 +1,105 @@
+use rand::Rng;
+use rand_distr::{Distribution, Poisson};
+use serde::Deserialize;
+
+/// Classical evolutionary theory of aging the age-specific mutations follow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GeneticMode {
+    /// Deleterious mutations raising the hazard only inside an age window, so
+    /// that late-acting ones escape selection.
+    MutationAccumulation,
+    /// Alleles raising fertility before their onset age and the hazard after it.
+    AntagonisticPleiotropy,
+}
+
+/// One mutation carried by an agent, acting relative to its age.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AgeSpecificMutation {
+    pub onset: f64,
+    /// Length of the window of extra hazard, infinite for pleiotropic alleles.
+    pub duration: f64,
+    /// Hazard added from `onset` to `onset + duration`.
+    pub hazard: f64,
+    /// Relative fertility gain before `onset`.
+    pub early_fertility: f64,
+}
+
+/// How new age-specific mutations arise and what they do.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgeSpecificMutationParameters {
+    pub mode: GeneticMode,
+    /// Mean number of new mutations per offspring, Poisson distributed.
+    pub rate: f64,
+    /// Onset ages are drawn uniformly in [min, max].
+    pub onset_range: [f64; 2],
+    pub hazard_effect: f64,
+    /// Width of the hazard window, mutation accumulation only.
+    #[serde(default)]
+    pub window: f64,
+    /// Relative early fertility gain, antagonistic pleiotropy only.
+    #[serde(default)]
+    pub fertility_effect: f64,
+}
+
+impl AgeSpecificMutationParameters {
+    fn new_mutation(&self, rng: &mut impl Rng) -> AgeSpecificMutation {
+        let onset = rng.gen_range(self.onset_range[0]..=self.onset_range[1]);
+        match self.mode {
+            GeneticMode::MutationAccumulation => AgeSpecificMutation {
+                onset,
+                duration: self.window,
+                hazard: self.hazard_effect,
+                early_fertility: 0.0,
+            },
+            GeneticMode::AntagonisticPleiotropy => AgeSpecificMutation {
+                onset,
+                duration: f64::INFINITY,
+                hazard: self.hazard_effect,
+                early_fertility: self.fertility_effect,
+            },
+        }
+    }
+
+    /// Mutations of an offspring: each parental mutation is passed on with
+    /// probability 1/2, as for a heterozygous locus, then new ones are added.
+    pub fn inherit(
+        &self,
+        father: &[AgeSpecificMutation],
+        mother: &[AgeSpecificMutation],
+        rng: &mut impl Rng,
+    ) -> Vec<AgeSpecificMutation> {
+        let mut mutations: Vec<AgeSpecificMutation> = father
+            .iter()
+            .chain(mother)
+            .filter(|_| rng.gen::<f64>() < 0.5)
+            .copied()
+            .collect();
+        if self.rate > 0.0 {
+            let new_mutations = Poisson::new(self.rate).unwrap().sample(rng) as usize;
+            mutations.extend((0..new_mutations).map(|_| self.new_mutation(rng)));
+        }
+        mutations
+    }
+}
+
+/// Extra hazard of the mutations integrated over `[from, to]`.
+pub fn mutation_cumulative_hazard(mutations: &[AgeSpecificMutation], from: f64, to: f64) -> f64 {
+    mutations
+        .iter()
+        .map(|mutation| {
+            let overlap = to.min(mutation.onset + mutation.duration) - from.max(mutation.onset);
+            mutation.hazard * overlap.max(0.0)
+        })
+        .sum()
+}
+
+/// Factor applied to the probability of reproducing at `age`.
+pub fn mutation_fertility_factor(mutations: &[AgeSpecificMutation], age: f64) -> f64 {
+    mutations
+        .iter()
+        .filter(|mutation| age < mutation.onset)
+        .map(|mutation| 1.0 + mutation.early_fertility)
+        .product()
+}
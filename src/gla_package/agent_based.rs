@@ -1,118 +1,295 @@
-use peroxide::fuga::GaussLegendre;
-use peroxide::numerical::integral::integrate;
 use rand::seq::SliceRandom;
-use rand_distr::{Distribution, Normal, num_traits::Float};
+use rand::Rng;
+use rand_distr::{Distribution, Normal};
 use rayon::prelude::*;
 use std::iter::zip;
 
-#[derive(Clone)]
+use crate::gla_package::age_specific::{
+    mutation_cumulative_hazard, mutation_fertility_factor, AgeSpecificMutation,
+    AgeSpecificMutationParameters,
+};
+use crate::gla_package::config::FertilityFunction;
+use crate::gla_package::density::DensityRegulation;
+use crate::gla_package::environment::HazardScaling;
+use crate::gla_package::litter::LitterParameters;
+use crate::gla_package::mating::{form_couples, MateChoice, MatingCorrelations, MatingParameters};
+use crate::gla_package::hazard::HazardModel;
+use crate::gla_package::heritable::{
+    Genotype, HeritableTrait, InheritedParameter, OffspringTemplate, TraitLocation,
+};
+use crate::gla_package::tradeoff::{tradeoff_factor, Tradeoff};
+use crate::gla_package::rng::substream;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct Agent {
+    /// Unique within a replicate, in order of birth after the founders.
+    pub id: u64,
+    /// Parents' ids, none for founders.
+    pub mother: Option<u64>,
+    pub father: Option<u64>,
+    /// Id of the current mate under monogamy.
+    pub partner: Option<u64>,
+    /// Time of birth, negative for founders older than 0 at the start.
+    pub birth_time: f64,
     pub age: f64,
     pub female: bool,
-    pub aging_parameters: Vec<f64>,
-    pub learning_parameters: Vec<f64>,
-    pub growth_parameters: Vec<f64>,
+    /// One parameter vector per component of the hazard model.
+    pub hazard_parameters: Vec<Vec<f64>>,
+    /// Both sexes carry both schedules and pass them on, but only express
+    /// the one of their own sex.
+    pub female_fertility_parameters: Vec<f64>,
+    pub male_fertility_parameters: Vec<f64>,
+    /// Values of the neutral traits.
+    pub neutral_parameters: Vec<f64>,
+    /// Proportion of sons, expressed by females only.
+    pub sex_ratio: f64,
+    /// Alleles of each heritable trait, empty for traits without loci, which
+    /// are inherited according to their `inheritance`.
+    pub genome: Vec<Genotype>,
+    /// Mutation accumulation or antagonistic pleiotropy mutations carried.
+    pub age_specific_mutations: Vec<AgeSpecificMutation>,
 }
 
-pub fn initialize_population<'a, 'b>(
-    initial_population_size: usize,
-    aging_parameters: &[f64],
-    learning_parameters: &[f64],
-    growth_parameters: &[f64],
-    initial_age_distribution: [f64; 2],
-    initial_b_distribution: [f64; 2],
-    initial_lmax_distribution: [f64; 2],
-    initial_gmax_distribution: [f64; 2],
-    initial_female_proportion: f64,
-) -> Vec<Agent> {
-    let mut population = Vec::with_capacity(initial_population_size);
+impl Agent {
+    pub fn trait_value(&self, location: TraitLocation) -> f64 {
+        match location {
+            TraitLocation::Hazard { component, index } => self.hazard_parameters[component][index],
+            TraitLocation::Fertility { female: true, index } => self.female_fertility_parameters[index],
+            TraitLocation::Fertility { female: false, index } => self.male_fertility_parameters[index],
+            TraitLocation::Neutral { index } => self.neutral_parameters[index],
+            TraitLocation::SexRatio => self.sex_ratio,
+        }
+    }
 
-    let age_dist = Normal::new(initial_age_distribution[0], initial_age_distribution[1]).unwrap();
-    let b_dist = Normal::new(initial_b_distribution[0], initial_b_distribution[1]).unwrap();
-    let lmax_dist =
-        Normal::new(initial_lmax_distribution[0], initial_lmax_distribution[1]).unwrap();
-    let gmax_dist =
-        Normal::new(initial_gmax_distribution[0], initial_gmax_distribution[1]).unwrap();
+    pub fn set_trait_value(&mut self, location: TraitLocation, value: f64) {
+        match location {
+            TraitLocation::Hazard { component, index } => self.hazard_parameters[component][index] = value,
+            TraitLocation::Fertility { female: true, index } => self.female_fertility_parameters[index] = value,
+            TraitLocation::Fertility { female: false, index } => self.male_fertility_parameters[index] = value,
+            TraitLocation::Neutral { index } => self.neutral_parameters[index] = value,
+            TraitLocation::SexRatio => self.sex_ratio = value,
+        }
+    }
 
-    for _ in 0..initial_population_size {
-        let age: f64 = age_dist.sample(&mut rand::thread_rng()).max(0.0).round();
-        let female: bool = rand::random::<f64>() < initial_female_proportion;
-        let b = b_dist.sample(&mut rand::thread_rng()).max(0.0);
-        let lmax = lmax_dist.sample(&mut rand::thread_rng()).max(0.0);
-        let gmax = gmax_dist.sample(&mut rand::thread_rng()).max(0.0);
+    /// Parameters of the fertility schedule the agent expresses.
+    pub fn fertility_parameters(&self) -> &[f64] {
+        if self.female {
+            &self.female_fertility_parameters
+        } else {
+            &self.male_fertility_parameters
+        }
+    }
+}
 
-        let mut agent_aging_parameters = aging_parameters.to_owned();
-        agent_aging_parameters[1] = b;
+/// Founders start from the baseline parameters, heritable traits aside.
+#[derive(Clone, Debug)]
+pub struct InitialPopulationParameters {
+    pub size: usize,
+    pub female_proportion: f64,
+    /// [mean, standard deviation] of the founders' ages.
+    pub age_distribution: [f64; 2],
+    pub female_fertility_parameters: Vec<f64>,
+    pub male_fertility_parameters: Vec<f64>,
+    pub neutral_parameters: Vec<f64>,
+    pub sex_ratio: f64,
+}
 
-        let mut agent_learning_parameters = learning_parameters.to_owned();
-        agent_learning_parameters[0] = lmax;
+/// How the hazard integrated over a time step becomes a probability of dying.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeathProbability {
+    /// `1 - exp(-H)`, the probability of not surviving the step.
+    #[default]
+    Exact,
+    /// `H` itself, only valid for small hazards; kept to compare with older runs.
+    Linear,
+}
 
-        let mut agent_growth_parameters = growth_parameters.to_owned();
-        agent_growth_parameters[0] = gmax;
+impl DeathProbability {
+    pub fn from_cumulative_hazard(&self, cumulative_hazard: f64) -> f64 {
+        match self {
+            DeathProbability::Exact => 1.0 - (-cumulative_hazard).exp(),
+            DeathProbability::Linear => cumulative_hazard,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DeathParameters {
+    pub time_step: f64,
+    pub death_probability: DeathProbability,
+    pub remove_non_reproducing: bool,
+    /// Schedules whose end gives the age at menopause of each agent.
+    pub female_fertility_function: FertilityFunction,
+    pub male_fertility_function: FertilityFunction,
+    /// Tradeoffs scaling the hazard.
+    pub survival_tradeoffs: Vec<Tradeoff>,
+    pub density_regulation: Option<DensityRegulation>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReproductionParameters {
+    pub assortative_mating: bool,
+    /// Tradeoffs scaling the probability of reproducing.
+    pub fertility_tradeoffs: Vec<Tradeoff>,
+    pub population_cap: usize,
+    pub age_specific_mutations: Option<AgeSpecificMutationParameters>,
+    /// Inheritance of the parameters that are not heritable traits.
+    pub offspring: OffspringTemplate,
+    pub density_regulation: Option<DensityRegulation>,
+    pub mating: MatingParameters,
+    pub mate_choice: Option<MateChoice>,
+    pub litter: LitterParameters,
+}
 
-        let agent = Agent {
+/// Outcome of the reproduction of a step.
+#[derive(Clone, Debug)]
+pub struct ReproductionSummary {
+    pub births: usize,
+    /// Correlations within the couples that formed, under mate choice only.
+    pub mating_correlations: Option<MatingCorrelations>,
+}
+
+pub fn initialize_population(
+    parameters: &InitialPopulationParameters,
+    baseline_parameters: &[Vec<f64>],
+    traits: &[HeritableTrait],
+    rng: &mut impl Rng,
+) -> Vec<Agent> {
+    let mut population = Vec::with_capacity(parameters.size);
+
+    let age_dist = Normal::new(parameters.age_distribution[0], parameters.age_distribution[1]).unwrap();
+
+    for id in 0..parameters.size as u64 {
+        let age: f64 = age_dist.sample(rng).max(0.0).round();
+        let female: bool = rng.gen::<f64>() < parameters.female_proportion;
+
+        let mut agent = Agent {
+            id,
+            mother: None,
+            father: None,
+            partner: None,
+            birth_time: -age,
             age,
             female,
-            aging_parameters: agent_aging_parameters,
-            learning_parameters: agent_learning_parameters,
-            growth_parameters: agent_growth_parameters,
+            hazard_parameters: baseline_parameters.to_owned(),
+            female_fertility_parameters: parameters.female_fertility_parameters.clone(),
+            male_fertility_parameters: parameters.male_fertility_parameters.clone(),
+            neutral_parameters: parameters.neutral_parameters.clone(),
+            sex_ratio: parameters.sex_ratio,
+            genome: Vec::with_capacity(traits.len()),
+            age_specific_mutations: Vec::new(),
         };
+        for heritable_trait in traits {
+            let (genotype, value) = match &heritable_trait.genetics {
+                Some(genetics) => {
+                    let genotype = heritable_trait.founder_genotype(genetics, rng);
+                    let value = heritable_trait.phenotype(genetics, &genotype, rng);
+                    (genotype, value)
+                }
+                None => (Vec::new(), heritable_trait.founder_value(rng)),
+            };
+            agent.genome.push(genotype);
+            agent.set_trait_value(heritable_trait.location, value);
+        }
 
         population.push(agent);
     }
     population
 }
 
+/// Integral of the hazard over `[age, age + time_step]`, age-specific mutations included.
+pub fn get_cumulative_hazard_agent(agent: &Agent, time_step: f64, hazard_model: &HazardModel) -> f64 {
+    get_cumulative_hazard_with_parameters(agent, &agent.hazard_parameters, time_step, hazard_model)
+}
+
+/// Same as [`get_cumulative_hazard_agent`] with other hazard parameters.
+pub fn get_cumulative_hazard_with_parameters(
+    agent: &Agent,
+    hazard_parameters: &[Vec<f64>],
+    time_step: f64,
+    hazard_model: &HazardModel,
+) -> f64 {
+    let (from, to) = (agent.age, agent.age + time_step);
+    hazard_model.cumulative_hazard(from, to, hazard_parameters)
+        + mutation_cumulative_hazard(&agent.age_specific_mutations, from, to)
+}
+
 pub fn get_proba_of_death_agent(
     agent: &Agent,
     time_step: f64,
-    aging_intermediate_closure: &dyn Fn(f64, &[f64], &[f64], &[f64]) -> f64,
+    death_probability: DeathProbability,
+    hazard_model: &HazardModel,
 ) -> f64 {
-    integrate(
-        |x: f64| -> f64 {
-            aging_intermediate_closure(
-                x,
-                &agent.aging_parameters,
-                &agent.learning_parameters,
-                &agent.growth_parameters,
-            )
-        },
-        (agent.age, agent.age + time_step),
-        GaussLegendre(5),
-    )
+    death_probability.from_cumulative_hazard(get_cumulative_hazard_agent(
+        agent,
+        time_step,
+        hazard_model,
+    ))
 }
 
+/// Draws whether `agent` dies during the step, with one of its hazard
+/// parameters scaled by the environment. `density_hazard` is the extrinsic
+/// hazard due to density, which tradeoffs do not scale.
 pub fn get_death_agent(
     agent: &Agent,
-    time_step: f64,
-    aging_intermediate_closure: &dyn Fn(f64, &[f64], &[f64], &[f64]) -> f64,
-    remove_non_reproducing: bool,
-    male_menopause: f64,
-    female_menopause: f64,
+    parameters: &DeathParameters,
+    hazard_model: &HazardModel,
+    environment: Option<HazardScaling>,
+    density_hazard: f64,
+    rng: &mut impl Rng,
 ) -> bool {
-    let proba_of_death = get_proba_of_death_agent(agent, time_step, aging_intermediate_closure);
-    if !remove_non_reproducing {
-        return rand::random::<f64>() < proba_of_death;
+    let hazard = match environment {
+        Some(scaling) => {
+            let mut hazard_parameters = agent.hazard_parameters.clone();
+            hazard_parameters[scaling.component][scaling.index] *= scaling.factor;
+            get_cumulative_hazard_with_parameters(agent, &hazard_parameters, parameters.time_step, hazard_model)
+        }
+        None => get_cumulative_hazard_agent(agent, parameters.time_step, hazard_model),
+    };
+    let cumulative_hazard = hazard
+        * tradeoff_factor(&parameters.survival_tradeoffs, agent)
+        + density_hazard * parameters.time_step;
+    let proba_of_death = parameters.death_probability.from_cumulative_hazard(cumulative_hazard);
+    if !parameters.remove_non_reproducing {
+        return rng.gen::<f64>() < proba_of_death;
     }
 
-    let menopause_age = if agent.female { female_menopause } else { male_menopause };
-    if menopause_age.is_nan() || agent.age <= menopause_age {
-        return rand::random::<f64>() < proba_of_death;
+    let fertility_function = if agent.female {
+        parameters.female_fertility_function
+    } else {
+        parameters.male_fertility_function
+    };
+    let menopause_age = fertility_function.menopause_age(agent.fertility_parameters());
+    if agent.age <= menopause_age {
+        return rng.gen::<f64>() < proba_of_death;
     }
     true
 }
 
-pub fn get_death_population<F: Fn(f64, &[f64], &[f64], &[f64]) -> f64 + Send + Sync>(
+pub fn get_death_population(
     population: &mut Vec<Agent>,
-    time_step: f64,
-    aging_intermediate_closure: &F,
-    remove_non_reproducing: bool,
-    male_menopause: f64,
-    female_menopause: f64,
-) {
+    parameters: &DeathParameters,
+    hazard_model: &HazardModel,
+    environment: Option<HazardScaling>,
+    rng: &mut impl Rng,
+) -> usize {
+    let class_densities = parameters
+        .density_regulation
+        .as_ref()
+        .map(|regulation| regulation.class_densities(population));
+    let step_seed: u64 = rng.gen();
     let death_test_parallel = population
         .par_iter()
-        .map(|agent| get_death_agent(agent, time_step, aging_intermediate_closure, remove_non_reproducing, male_menopause, female_menopause))
+        .enumerate()
+        .map(|(index, agent)| {
+            let mut agent_rng = substream(step_seed, index as u64);
+            let density_hazard = match (&parameters.density_regulation, &class_densities) {
+                (Some(regulation), Some(densities)) => regulation.hazard(regulation.density(densities, agent)),
+                _ => 0.0,
+            };
+            get_death_agent(agent, parameters, hazard_model, environment, density_hazard, &mut agent_rng)
+        })
         .collect::<Vec<_>>();
     let mut dead_agent_indexes: Vec<usize> = death_test_parallel
         .iter()
@@ -127,148 +304,174 @@ pub fn get_death_population<F: Fn(f64, &[f64], &[f64], &[f64]) -> f64 + Send + S
     for index in dead_agent_indexes.iter() {
         population.swap_remove(*index);
     }
+    dead_agent_indexes.len()
 }
 
-pub fn increment_age_population(population: &mut Vec<Agent>, time_step: f64) {
+pub fn increment_age_population(population: &mut [Agent], time_step: f64) {
     for agent in population.iter_mut() {
         agent.age += time_step;
     }
 }
 
-pub fn sort_population_by_age(population: &mut Vec<Agent>) {
+pub fn sort_population_by_age(population: &mut [Agent]) {
     population.sort_by(|a, b| a.age.partial_cmp(&b.age).unwrap());
 }
 
-pub fn create_couples(population: &Vec<Agent>) -> Vec<(&Agent, &Agent)> {
-    let mut female_population = population
-        .iter()
-        .filter(|a| a.female)
-        .map(|a| a)
-        .collect::<Vec<_>>();
-    let mut male_population = population
-        .iter()
-        .filter(|a| !a.female)
-        .map(|a| a)
-        .collect::<Vec<_>>();
-
-    if male_population.len() > female_population.len() {
-        male_population = male_population[..female_population.len()].to_vec();
-    } else {
-        female_population = female_population[..male_population.len()].to_vec();
-    }
-
-    zip(male_population, female_population).collect::<Vec<_>>()
-}
-
 pub fn reproduction_test_couple(
     couple: &(&Agent, &Agent),
-    normalized_male_fertility_closure: &Box<impl Fn(f64) -> f64>,
-    normalized_female_fertility_closure: &Box<impl Fn(f64) -> f64>,
-    tradeoff: bool,
-    start_b: f64,
+    normalized_male_fertility_closure: &impl Fn(f64, &[f64]) -> f64,
+    normalized_female_fertility_closure: &impl Fn(f64, &[f64]) -> f64,
+    parameters: &ReproductionParameters,
+    density_factor: f64,
+    rng: &mut impl Rng,
 ) -> bool {
-
-    let mut tradeoff_male = 1f64;
-    let mut tradeoff_female = 1f64;
-    if tradeoff{
-        tradeoff_male = couple.0.aging_parameters[1]/start_b;
-        tradeoff_female = couple.1.aging_parameters[1]/start_b;
-    }
-    let male_chance_to_reproduce = normalized_male_fertility_closure(couple.0.age)*tradeoff_male;
-    let female_chance_to_reproduce = normalized_female_fertility_closure(couple.1.age)*tradeoff_female;
-
-    (rand::random::<f64>() < male_chance_to_reproduce)
-        && (rand::random::<f64>() < female_chance_to_reproduce)
+    let tradeoff_male = tradeoff_factor(&parameters.fertility_tradeoffs, couple.0);
+    let tradeoff_female = tradeoff_factor(&parameters.fertility_tradeoffs, couple.1);
+    let male_chance_to_reproduce =
+        normalized_male_fertility_closure(couple.0.age, couple.0.fertility_parameters())*tradeoff_male
+            * mutation_fertility_factor(&couple.0.age_specific_mutations, couple.0.age);
+    let female_chance_to_reproduce =
+        normalized_female_fertility_closure(couple.1.age, couple.1.fertility_parameters())*tradeoff_female
+            * mutation_fertility_factor(&couple.1.age_specific_mutations, couple.1.age)
+            * density_factor;
+
+    (rng.gen::<f64>() < male_chance_to_reproduce)
+        && (rng.gen::<f64>() < female_chance_to_reproduce)
 }
 
-pub fn mutate_parameter(param: &mut f64, mutation_rate: f64, mutation_strength: f64) {
-    if rand::random::<f64>() < mutation_rate {
-        let mutation_dist = Normal::new(*param, mutation_strength).unwrap();
-        *param = mutation_dist.sample(&mut rand::thread_rng()).max(0.0);
-    }
+fn inherit_parameters(template: &[InheritedParameter], father: &[f64], mother: &[f64]) -> Vec<f64> {
+    template
+        .iter()
+        .enumerate()
+        .map(|(index, parameter)| parameter.value(father[index], mother[index]))
+        .collect()
 }
 
+/// Offspring of a (father, mother) couple: its parameters follow `offspring`,
+/// then its traits are inherited from the parents. Its id and birth time are
+/// set by the simulation once it joins the population.
 pub fn reproduction_couple(
     couple: &(&Agent, &Agent),
-    aging_parameters: &[f64],
-    learning_parameters: &[f64],
-    growth_parameters: &[f64],
-    mutable_b: bool,
-    mutable_lmax: bool,
-    mutable_gmax: bool,
-    b_mutation_rate: f64,
-    lmax_mutation_rate: f64,
-    gmax_mutation_rate: f64,
-    b_mutation_strength: f64,
-    lmax_mutation_strength: f64,
-    gmax_mutation_strength: f64,
+    offspring: &OffspringTemplate,
+    traits: &[HeritableTrait],
+    age_specific_mutations: Option<&AgeSpecificMutationParameters>,
+    rng: &mut impl Rng,
 ) -> Agent {
-    let mut b = (couple.0.aging_parameters[1] + couple.1.aging_parameters[1]) / 2.0;
-    if mutable_b {
-        mutate_parameter(&mut b, b_mutation_rate, b_mutation_strength);
-    }
-    let mut lmax = (couple.0.learning_parameters[0] + couple.1.learning_parameters[0]) / 2.0;
-    if mutable_lmax {
-        mutate_parameter(&mut lmax, lmax_mutation_rate, lmax_mutation_strength);
+    let mut agent = Agent {
+        id: 0,
+        mother: Some(couple.1.id),
+        father: Some(couple.0.id),
+        partner: None,
+        birth_time: 0.0,
+        age: 0.0,
+        female: false,
+        hazard_parameters: offspring
+            .hazard
+            .iter()
+            .enumerate()
+            .map(|(component, template)| {
+                inherit_parameters(
+                    template,
+                    &couple.0.hazard_parameters[component],
+                    &couple.1.hazard_parameters[component],
+                )
+            })
+            .collect(),
+        female_fertility_parameters: inherit_parameters(
+            &offspring.female_fertility,
+            &couple.0.female_fertility_parameters,
+            &couple.1.female_fertility_parameters,
+        ),
+        male_fertility_parameters: inherit_parameters(
+            &offspring.male_fertility,
+            &couple.0.male_fertility_parameters,
+            &couple.1.male_fertility_parameters,
+        ),
+        neutral_parameters: inherit_parameters(
+            &offspring.neutral,
+            &couple.0.neutral_parameters,
+            &couple.1.neutral_parameters,
+        ),
+        sex_ratio: offspring.sex_ratio.value(couple.0.sex_ratio, couple.1.sex_ratio),
+        genome: Vec::with_capacity(traits.len()),
+        age_specific_mutations: Vec::new(),
+    };
+
+    for (trait_index, heritable_trait) in traits.iter().enumerate() {
+        let location = heritable_trait.location;
+        let (genotype, value) = match &heritable_trait.genetics {
+            Some(genetics) => {
+                let (father, mother) = (&couple.0.genome[trait_index], &couple.1.genome[trait_index]);
+                let maternal = heritable_trait.gamete(genetics, mother, rng);
+                let paternal = heritable_trait.gamete(genetics, father, rng);
+                let genotype: Genotype = zip(maternal, paternal).map(|(m, p)| [m, p]).collect();
+                let value = heritable_trait.phenotype(genetics, &genotype, rng);
+                (genotype, value)
+            }
+            None => {
+                let inherited = heritable_trait.inheritance.value(
+                    offspring.parameter(location).baseline,
+                    couple.0.trait_value(location),
+                    couple.1.trait_value(location),
+                );
+                (Vec::new(), heritable_trait.mutate(inherited, rng))
+            }
+        };
+        agent.genome.push(genotype);
+        agent.set_trait_value(location, value);
     }
-    let mut gmax = (couple.0.growth_parameters[0] + couple.1.growth_parameters[0]) / 2.0;
-    if mutable_gmax {
-        mutate_parameter(&mut gmax, gmax_mutation_rate, gmax_mutation_strength);
+    if let Some(parameters) = age_specific_mutations {
+        agent.age_specific_mutations =
+            parameters.inherit(&couple.0.age_specific_mutations, &couple.1.age_specific_mutations, rng);
     }
 
-    let female: bool = rand::random::<f64>() < 0.5;
-
-    let mut agent_aging_parameters = aging_parameters.to_owned();
-    agent_aging_parameters[1] = b;
-
-    let mut agent_learning_parameters = learning_parameters.to_owned();
-    agent_learning_parameters[0] = lmax;
-
-    let mut agent_growth_parameters = growth_parameters.to_owned();
-    agent_growth_parameters[0] = gmax;
-
-    Agent {
-        age: 0.0,
-        female: female,
-        aging_parameters: agent_aging_parameters,
-        learning_parameters: agent_learning_parameters,
-        growth_parameters: agent_growth_parameters,
-    }
+    // The mother sets the sex of her offspring.
+    agent.female = rng.gen::<f64>() < 1.0 - couple.1.sex_ratio;
+    agent
 }
 
 pub fn get_reproduction_population(
     population: &mut Vec<Agent>,
-    assortative_mating: bool,
-    normalized_male_fertility_closure: &Box<impl Fn(f64) -> f64>,
-    normalized_female_fertility_closure: &Box<impl Fn(f64) -> f64>,
-    tradeoff: bool,
-    start_b: f64,
-    population_cap: usize,
-    mutable_b: bool,
-    mutable_lmax: bool,
-    mutable_gmax: bool,
-    b_mutation_rate: f64,
-    lmax_mutation_rate: f64,
-    gmax_mutation_rate: f64,
-    b_mutation_strength: f64,
-    lmax_mutation_strength: f64,
-    gmax_mutation_strength: f64,
-) {
-    if assortative_mating {
+    normalized_male_fertility_closure: &impl Fn(f64, &[f64]) -> f64,
+    normalized_female_fertility_closure: &impl Fn(f64, &[f64]) -> f64,
+    parameters: &ReproductionParameters,
+    traits: &[HeritableTrait],
+    rng: &mut impl Rng,
+) -> ReproductionSummary {
+    if parameters.assortative_mating {
         sort_population_by_age(population);
     }else{
-        population.shuffle(&mut rand::thread_rng());
+        population.shuffle(rng);
     }
-    let couples = create_couples(population);
+    let class_densities = parameters
+        .density_regulation
+        .as_ref()
+        .map(|regulation| regulation.class_densities(population));
+    let couples: Vec<(&Agent, &Agent)> = form_couples(population, &parameters.mating, parameters.mate_choice.as_ref(), rng)
+        .into_iter()
+        .map(|(male, female)| (&population[male], &population[female]))
+        .collect();
+    let mating_correlations = parameters
+        .mate_choice
+        .as_ref()
+        .map(|_| MatingCorrelations::new(&couples, traits));
     let reproduction_test = couples
         .iter()
         .map(|couple| {
+            // Density acts on the mother, who bears the offspring.
+            let density_factor = match (&parameters.density_regulation, &class_densities) {
+                (Some(regulation), Some(densities)) => {
+                    regulation.fertility_factor(regulation.density(densities, couple.1))
+                }
+                _ => 1.0,
+            };
             reproduction_test_couple(
                 couple,
                 normalized_male_fertility_closure,
                 normalized_female_fertility_closure,
-                tradeoff,
-                start_b,
+                parameters,
+                density_factor,
+                rng,
             )
         })
         .collect::<Vec<_>>();
@@ -280,82 +483,243 @@ pub fn get_reproduction_population(
         .map(|(index, _)| index)
         .collect();
 
-    let mut new_babies: Vec<Agent> = successful_couples_indexes
-        .iter()
-        .map(|index| {
-            reproduction_couple(
-                &couples[*index],
-                &population[*index].aging_parameters,
-                &population[*index].learning_parameters,
-                &population[*index].growth_parameters,
-                mutable_b,
-                mutable_lmax,
-                mutable_gmax,
-                b_mutation_rate,
-                lmax_mutation_rate,
-                gmax_mutation_rate,
-                b_mutation_strength,
-                lmax_mutation_strength,
-                gmax_mutation_strength,
-            )
-        })
-        .collect();
-
-    new_babies.shuffle(&mut rand::thread_rng());
-    if new_babies.len() > population_cap as usize - population.len() {
-        new_babies = new_babies[..(population_cap as usize - population.len())].to_vec();
+    let mut new_babies: Vec<Agent> = Vec::with_capacity(successful_couples_indexes.len());
+    for index in successful_couples_indexes {
+        let couple = &couples[index];
+        for _ in 0..parameters.litter.litter_size(couple.1.age, rng) {
+            new_babies.push(reproduction_couple(
+                couple,
+                &parameters.offspring,
+                traits,
+                parameters.age_specific_mutations.as_ref(),
+                rng,
+            ));
+        }
     }
 
+    new_babies.shuffle(rng);
+    // A population built above the cap gets no births until it falls below it.
+    new_babies.truncate(parameters.population_cap.saturating_sub(population.len()));
+
+    let births = new_babies.len();
     population.extend(new_babies);
+    ReproductionSummary {
+        births,
+        mating_correlations,
+    }
 }
 
-pub fn get_population_b_stats(population: &Vec<Agent>) -> (f64, f64) {
-    let b_values = population
-        .iter()
-        .map(|agent| agent.aging_parameters[1])
-        .collect::<Vec<_>>();
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct TraitStats {
+    pub mean: f64,
+    pub variance: f64,
+    pub min: f64,
+    pub max: f64,
+    pub q05: f64,
+    pub q25: f64,
+    pub median: f64,
+    pub q75: f64,
+    pub q95: f64,
+}
+
+/// Linearly interpolated quantile of already sorted values, NaN when empty.
+pub fn quantile(sorted_values: &[f64], probability: f64) -> f64 {
+    if sorted_values.is_empty() {
+        return f64::NAN;
+    }
+    let position = probability * (sorted_values.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    let weight = position - lower as f64;
+    sorted_values[lower] * (1.0 - weight) + sorted_values[upper] * weight
+}
 
-    let b_mean = b_values.par_iter().sum::<f64>() / b_values.len() as f64;
+pub fn get_trait_stats(mut values: Vec<f64>) -> TraitStats {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
 
-    let b_variance = b_values
-        .par_iter()
-        .map(|b| (b - b_mean).powi(2))
+    let variance = values
+        .iter()
+        .map(|value| (value - mean).powi(2))
         .sum::<f64>()
-        / b_values.len() as f64;
+        / values.len() as f64;
+
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    TraitStats {
+        mean,
+        variance,
+        min: quantile(&values, 0.0),
+        max: quantile(&values, 1.0),
+        q05: quantile(&values, 0.05),
+        q25: quantile(&values, 0.25),
+        median: quantile(&values, 0.5),
+        q75: quantile(&values, 0.75),
+        q95: quantile(&values, 0.95),
+    }
+}
 
-    (b_mean, b_variance)
+/// Statistics of the value at `location` over the population.
+pub fn get_population_trait_stats(population: &[Agent], location: TraitLocation) -> TraitStats {
+    get_trait_stats(population.iter().map(|agent| agent.trait_value(location)).collect())
 }
 
-pub fn get_population_lmax_stats(population: &Vec<Agent>) -> (f64, f64){
-    let lmax_values = population
-        .iter()
-        .map(|agent| agent.learning_parameters[0])
-        .collect::<Vec<_>>();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gla_package::hazard::{Growth, GompertzMakeham, Learning};
+
+    /// GLA model without floor, so that only the components contribute.
+    fn gla_hazard() -> HazardModel {
+        HazardModel::new(0.0)
+            .with_component("aging", Box::new(GompertzMakeham), vec![0.0; 3])
+            .with_component("learning", Box::new(Learning), vec![0.0; 3])
+            .with_component("growth", Box::new(Growth), vec![0.0; 2])
+    }
 
-    let lmax_mean = lmax_values.par_iter().sum::<f64>() / lmax_values.len() as f64;
+    /// Gompertz-Makeham agent with the learning and growth terms switched off.
+    fn gompertz_makeham_agent(age: f64, a: f64, b: f64, c: f64) -> Agent {
+        Agent {
+            id: 0,
+            mother: None,
+            father: None,
+            partner: None,
+            birth_time: -age,
+            age,
+            female: true,
+            hazard_parameters: vec![vec![a, b, c], vec![0.0, 39.0, 0.1], vec![0.0, 0.09]],
+            female_fertility_parameters: Vec::new(),
+            male_fertility_parameters: Vec::new(),
+            neutral_parameters: Vec::new(),
+            sex_ratio: 0.5,
+            genome: Vec::new(),
+            age_specific_mutations: Vec::new(),
+        }
+    }
 
-    let lmax_variance = lmax_values
-        .par_iter()
-        .map(|lmax| (lmax - lmax_mean).powi(2))
-        .sum::<f64>()
-        / lmax_values.len() as f64;
+    /// 1 - S(x + dt) / S(x) with S the Gompertz-Makeham survival function.
+    fn closed_form_death_probability(age: f64, time_step: f64, a: f64, b: f64, c: f64) -> f64 {
+        let cumulative_hazard =
+            c * time_step + a / b * ((b * (age + time_step)).exp() - (b * age).exp());
+        1.0 - (-cumulative_hazard).exp()
+    }
 
-    (lmax_mean, lmax_variance)
-}
+    #[test]
+    fn death_probability_matches_gompertz_makeham_survival() {
+        let hazard = gla_hazard();
+        let (a, b, c) = (0.00275961297460256, 0.14, 0.025201676835511704);
+
+        for &time_step in &[0.1, 1.0, 5.0] {
+            for &age in &[0.0, 20.0, 50.0, 80.0, 100.0] {
+                let agent = gompertz_makeham_agent(age, a, b, c);
+                let expected = closed_form_death_probability(age, time_step, a, b, c);
+
+                let exact = get_proba_of_death_agent(&agent, time_step, DeathProbability::Exact, &hazard);
+                let linear = get_proba_of_death_agent(&agent, time_step, DeathProbability::Linear, &hazard);
+
+                assert!(
+                    (exact - expected).abs() < 1e-6,
+                    "age {} step {}: exact {} closed form {}",
+                    age,
+                    time_step,
+                    exact,
+                    expected
+                );
+                assert!((0.0..=1.0).contains(&exact));
+                // The linear approximation always overestimates the risk.
+                assert!(linear >= expected);
+            }
+        }
+    }
 
-pub fn get_population_gmax_stats(population: &Vec<Agent>) -> (f64, f64){
-    let gmax_values = population
-        .iter()
-        .map(|agent| agent.growth_parameters[0])
-        .collect::<Vec<_>>();
+    #[test]
+    fn linear_approximation_breaks_down_for_large_hazards() {
+        let hazard = gla_hazard();
+        let (a, b, c) = (0.00275961297460256, 0.14, 0.025201676835511704);
+        let agent = gompertz_makeham_agent(60.0, a, b, c);
 
-    let gmax_mean = gmax_values.par_iter().sum::<f64>() / gmax_values.len() as f64;
+        let exact = get_proba_of_death_agent(&agent, 1.0, DeathProbability::Exact, &hazard);
+        let linear = get_proba_of_death_agent(&agent, 1.0, DeathProbability::Linear, &hazard);
+        let expected = closed_form_death_probability(60.0, 1.0, a, b, c);
 
-    let gmax_variance = gmax_values
-        .par_iter()
-        .map(|gmax| (gmax - gmax_mean).powi(2))
-        .sum::<f64>()
-        / gmax_values.len() as f64;
+        assert!(linear > 1.0);
+        assert!((exact - expected).abs() < 1e-6);
+
+        let young_agent = gompertz_makeham_agent(10.0, a, b, c);
+        let young_exact = get_proba_of_death_agent(&young_agent, 1.0, DeathProbability::Exact, &hazard);
+        let young_linear = get_proba_of_death_agent(&young_agent, 1.0, DeathProbability::Linear, &hazard);
+        assert!((young_exact - young_linear).abs() < 1e-3);
+    }
+
+    /// Agent whose parameters are all distinct from those of the other agents.
+    fn distinct_agent(id: u64) -> Agent {
+        let value = |offset: f64| id as f64 + offset;
+        Agent {
+            id,
+            female: id.is_multiple_of(2),
+            age: 25.0,
+            hazard_parameters: vec![
+                vec![value(0.1), value(0.2), value(0.3)],
+                vec![value(0.4), value(0.5), value(0.6)],
+                vec![value(0.7), value(0.8)],
+            ],
+            female_fertility_parameters: vec![value(0.01), value(0.02)],
+            male_fertility_parameters: vec![value(0.03), value(0.04)],
+            ..gompertz_makeham_agent(25.0, 0.0, 0.0, 0.0)
+        }
+    }
 
-    (gmax_mean, gmax_variance)
-}
\ No newline at end of file
+    #[test]
+    fn offspring_parameters_depend_only_on_their_parents() {
+        use crate::gla_package::heritable::Inheritance;
+        use crate::gla_package::rng::replicate_rng;
+
+        let founders: Vec<Agent> = (0..40).map(distinct_agent).collect();
+        let baseline_hazard = [vec![0.0; 3], vec![0.0; 3], vec![0.0; 2]];
+        let offspring = OffspringTemplate::baseline(&baseline_hazard, &[0.0; 2], &[0.0; 2], &[], 0.5)
+            .with_inheritance(TraitLocation::Hazard { component: 0, index: 1 }, Inheritance::Maternal)
+            .with_inheritance(TraitLocation::Hazard { component: 0, index: 2 }, Inheritance::Paternal)
+            .with_inheritance(TraitLocation::Hazard { component: 1, index: 0 }, Inheritance::Midparent)
+            .with_inheritance(TraitLocation::Fertility { female: true, index: 1 }, Inheritance::Paternal)
+            .with_inheritance(TraitLocation::Fertility { female: false, index: 0 }, Inheritance::Midparent);
+        let parameters = ReproductionParameters {
+            assortative_mating: false,
+            fertility_tradeoffs: Vec::new(),
+            population_cap: 1000,
+            age_specific_mutations: None,
+            offspring: offspring.clone(),
+            density_regulation: None,
+            mating: MatingParameters::default(),
+            mate_choice: None,
+            litter: LitterParameters::default(),
+        };
+        let always = |_: f64, _: &[f64]| 1.0;
+        let mut locations: Vec<TraitLocation> = Vec::new();
+        for (component, template) in offspring.hazard.iter().enumerate() {
+            locations.extend((0..template.len()).map(|index| TraitLocation::Hazard { component, index }));
+        }
+        for female in [true, false] {
+            locations.extend((0..2).map(|index| TraitLocation::Fertility { female, index }));
+        }
+
+        for reverse in [false, true] {
+            let mut population = founders.clone();
+            if reverse {
+                population.reverse();
+            }
+            let mut rng = replicate_rng(1, 0);
+            let births =
+                get_reproduction_population(&mut population, &always, &always, &parameters, &[], &mut rng).births;
+            assert_eq!(births, 20);
+
+            for newborn in &population[population.len() - births..] {
+                let mother = &founders[newborn.mother.unwrap() as usize];
+                let father = &founders[newborn.father.unwrap() as usize];
+                for &location in &locations {
+                    let expected = offspring
+                        .parameter(location)
+                        .value(father.trait_value(location), mother.trait_value(location));
+                    assert_eq!(newborn.trait_value(location), expected, "{:?}", location);
+                }
+            }
+        }
+    }
+}
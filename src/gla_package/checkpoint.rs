@@ -0,0 +1,157 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::gla_package::simulate::SimulationResult;
+use crate::gla_package::simulation::SimulationState;
+
+/// Snapshot of a replicate in progress, or complete but not yet written. Rows
+/// are only written to the results file once a replicate completes and the
+/// ones before it are written, so the rows produced so far travel with the
+/// population.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Checkpoint {
+    pub state: SimulationState,
+    pub results: Vec<SimulationResult>,
+    /// Length of the pedigree file when the checkpoint was saved, when there is one.
+    #[serde(default)]
+    pub pedigree_length: Option<u64>,
+}
+
+pub struct CheckpointStore {
+    directory: PathBuf,
+    interval: Option<usize>,
+}
+
+impl CheckpointStore {
+    pub fn new<P: AsRef<Path>>(directory: P, interval: Option<usize>) -> Self {
+        CheckpointStore {
+            directory: directory.as_ref().to_path_buf(),
+            interval,
+        }
+    }
+
+    fn path(&self, replicate_id: usize) -> PathBuf {
+        self.directory.join(format!("replicate_{}.json", replicate_id))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.interval.is_some()
+    }
+
+    /// True when a checkpoint is due after `steps` steps.
+    pub fn is_due(&self, steps: usize) -> bool {
+        match self.interval {
+            Some(interval) => steps.is_multiple_of(interval),
+            None => false,
+        }
+    }
+
+    /// Writes to a temporary file first so that a preemption during the write
+    /// never leaves a truncated checkpoint behind.
+    pub fn save(&self, checkpoint: &Checkpoint) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let path = self.path(checkpoint.state.replicate_id);
+        let temporary_path = path.with_extension("json.tmp");
+        let writer = BufWriter::new(File::create(&temporary_path)?);
+        serde_json::to_writer(writer, checkpoint)?;
+        fs::rename(temporary_path, path)
+    }
+
+    pub fn load(&self, replicate_id: usize) -> io::Result<Option<Checkpoint>> {
+        let path = self.path(replicate_id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(path)?);
+        Ok(Some(serde_json::from_reader(reader)?))
+    }
+
+    pub fn remove(&self, replicate_id: usize) -> io::Result<()> {
+        let path = self.path(replicate_id);
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the replicates already complete in a results file and truncates any
+/// rows of a replicate that was being written when the run stopped.
+///
+/// Replicates are written in order, so the complete ones are always
+/// `0..returned value`. Every row ends with a line terminator, so a last row
+/// without one was cut short and does not complete its replicate.
+pub fn completed_replicates<P: AsRef<Path>>(results_path: P, steps_per_replicate: usize) -> io::Result<usize> {
+    let results_path = results_path.as_ref();
+    if !results_path.exists() {
+        return Ok(0);
+    }
+    let file_length = fs::metadata(results_path)?.len();
+    if file_length == 0 {
+        return Ok(0);
+    }
+
+    let mut last_byte = [0];
+    let mut file = File::open(results_path)?;
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last_byte)?;
+    let terminated = last_byte[0] == b'\n';
+
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(results_path)?;
+    let replicate_column = reader
+        .headers()?
+        .iter()
+        .position(|header| header == "replicate_id")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "results file has no replicate_id column"))?;
+
+    let mut completed = 0;
+    let mut rows_in_replicate = 0;
+    let mut end_of_completed = reader.position().byte();
+    let mut record = csv::StringRecord::new();
+    while reader.read_record(&mut record)? {
+        if reader.position().byte() == file_length && !terminated {
+            break;
+        }
+        let replicate_id: usize = record
+            .get(replicate_column)
+            .and_then(|replicate_id| replicate_id.parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid replicate_id in results file"))?;
+        if replicate_id != completed {
+            break;
+        }
+        rows_in_replicate += 1;
+        if rows_in_replicate == steps_per_replicate {
+            completed += 1;
+            rows_in_replicate = 0;
+            end_of_completed = reader.position().byte();
+        }
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .open(results_path)?
+        .set_len(end_of_completed)?;
+    Ok(completed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completed_replicates_truncates_the_interrupted_one() {
+        let path = std::env::temp_dir().join(format!("completed_replicates_{}.csv", std::process::id()));
+        let complete = "time,replicate_id,population_size\n\
+                        0.0,0,10\n1.0,0,11\n2.0,0,12\n\
+                        0.0,1,10\n1.0,1,9\n2.0,1,8\n";
+        // Replicate 2 stopped in the middle of its last row, which was 2.0,2,123.
+        fs::write(&path, format!("{}0.0,2,10\n1.0,2,11\n2.0,2,12", complete)).unwrap();
+        assert_eq!(completed_replicates(&path, 3).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), complete);
+        // A second pass keeps the complete replicates as they are.
+        assert_eq!(completed_replicates(&path, 3).unwrap(), 2);
+        assert_eq!(fs::read_to_string(&path).unwrap(), complete);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,1086 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::gla_package::age_specific::AgeSpecificMutationParameters;
+use crate::gla_package::agent_based::DeathProbability;
+use crate::gla_package::density::DensityRegulation;
+use crate::gla_package::environment::{Environment, EnvironmentParameters};
+use crate::gla_package::genealogy::GenealogyParameters;
+use crate::gla_package::litter::{LitterParameters, LitterSize};
+use crate::gla_package::mating::{MateChoice, MatingParameters, MatingSystem, TraitPreference};
+use crate::gla_package::heritable::{
+    Boundary, Genetics, HeritableTrait, Inheritance, MutationKernel, OffspringTemplate, TraitLocation,
+};
+use crate::gla_package::tradeoff::{Tradeoff, TradeoffForm, TradeoffTarget};
+use crate::gla_package::hazard::{Growth, HazardKind, HazardModel, Learning};
+use crate::gla_package::gla::{
+    constant_fertility, fertility_brass_polynomial, fertility_coale_trussell, fertility_decreasing,
+    fertility_gamma, fertility_hadwiger, fertility_increasing, FertilityError, FertilityMaximum,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not read configuration file: {}", e),
+            ConfigError::Parse(e) => write!(f, "could not parse configuration file: {}", e),
+            ConfigError::Invalid(msg) => write!(f, "invalid configuration: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        ConfigError::Parse(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FertilityFunction {
+    Constant,
+    Increasing,
+    Decreasing,
+    BrassPolynomial,
+    Hadwiger,
+    Gamma,
+    CoaleTrussell,
+}
+
+impl FertilityFunction {
+    pub fn function(&self) -> fn(f64, &[f64]) -> f64 {
+        match self {
+            FertilityFunction::Constant => constant_fertility,
+            FertilityFunction::Increasing => fertility_increasing,
+            FertilityFunction::Decreasing => fertility_decreasing,
+            FertilityFunction::BrassPolynomial => fertility_brass_polynomial,
+            FertilityFunction::Hadwiger => fertility_hadwiger,
+            FertilityFunction::Gamma => fertility_gamma,
+            FertilityFunction::CoaleTrussell => fertility_coale_trussell,
+        }
+    }
+
+    pub fn parameter_names(&self) -> &[&'static str] {
+        match self {
+            FertilityFunction::Constant => &["c"],
+            FertilityFunction::Increasing
+            | FertilityFunction::Decreasing
+            | FertilityFunction::BrassPolynomial => &["c", "d", "w"],
+            FertilityFunction::Hadwiger => &["a", "b", "c"],
+            FertilityFunction::Gamma => &["r", "d", "k", "theta"],
+            FertilityFunction::CoaleTrussell => &["M", "m"],
+        }
+    }
+
+    /// Ages between which the schedule can be positive, with an infinite end
+    /// when fertility never stops.
+    pub fn support(&self, fertility_parameters: &[f64]) -> (f64, f64) {
+        match self {
+            FertilityFunction::Constant | FertilityFunction::Hadwiger => (0.0, f64::INFINITY),
+            FertilityFunction::Increasing
+            | FertilityFunction::Decreasing
+            | FertilityFunction::BrassPolynomial => (
+                fertility_parameters[1],
+                fertility_parameters[1] + fertility_parameters[2],
+            ),
+            FertilityFunction::Gamma => (fertility_parameters[1], f64::INFINITY),
+            FertilityFunction::CoaleTrussell => (15.0, 50.0),
+        }
+    }
+
+    /// Age after which the schedule is zero, infinite if fertility never stops.
+    pub fn menopause_age(&self, fertility_parameters: &[f64]) -> f64 {
+        self.support(fertility_parameters).1
+    }
+
+    /// Highest point of the schedule, in closed form for every family.
+    ///
+    /// For the linear ramps and the gamma density of shape 1 the maximum is
+    /// the limit at the open end of the window. Fails when the maximum is not
+    /// positive or not finite, since the schedule is divided by it.
+    pub fn maximum(&self, fertility_parameters: &[f64]) -> Result<FertilityMaximum, FertilityError> {
+        let p = fertility_parameters;
+        let maximum = match self {
+            FertilityFunction::Constant => FertilityMaximum { age: 0.0, value: p[0] },
+            FertilityFunction::Increasing => FertilityMaximum { age: p[1] + p[2], value: p[0] },
+            FertilityFunction::Decreasing => FertilityMaximum { age: p[1], value: p[0] },
+            FertilityFunction::BrassPolynomial => FertilityMaximum {
+                age: p[1] + p[2] / 3.0,
+                value: 4.0 * p[0] * p[2].powi(3) / 27.0,
+            },
+            FertilityFunction::Hadwiger => {
+                // Root of b^2 x^2 + 3/2 c x - b^2 c^2, where the log-derivative vanishes.
+                let (b, c) = (p[1], p[2]);
+                let age = c * (-1.5 + (2.25 + 4.0 * b.powi(4)).sqrt()) / (2.0 * b.powi(2));
+                FertilityMaximum { age, value: fertility_hadwiger(age, p) }
+            }
+            FertilityFunction::Gamma => {
+                let (r, d, k, theta) = (p[0], p[1], p[2], p[3]);
+                if k > 1.0 {
+                    let age = d + (k - 1.0) * theta;
+                    FertilityMaximum { age, value: fertility_gamma(age, p) }
+                } else if k == 1.0 {
+                    FertilityMaximum { age: d, value: r / theta }
+                } else {
+                    // The density diverges at d.
+                    FertilityMaximum { age: d, value: f64::INFINITY }
+                }
+            }
+            FertilityFunction::CoaleTrussell => (15..50)
+                .step_by(5)
+                .map(|group_start| {
+                    let age = group_start as f64 + 2.5;
+                    FertilityMaximum { age, value: fertility_coale_trussell(age, p) }
+                })
+                .fold(FertilityMaximum { age: 15.0, value: f64::NEG_INFINITY }, |best, group| {
+                    if group.value > best.value {
+                        group
+                    } else {
+                        best
+                    }
+                }),
+        };
+
+        if maximum.value == f64::INFINITY {
+            Err(FertilityError::UnboundedMaximum { age: maximum.age })
+        } else if maximum.value > 0.0 {
+            Ok(maximum)
+        } else {
+            Err(FertilityError::NonPositiveMaximum {
+                age: maximum.age,
+                value: maximum.value,
+            })
+        }
+    }
+}
+
+/// Additional term of the hazard, summed with the GLA components.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HazardComponentConfig {
+    pub name: String,
+    pub kind: HazardKind,
+    pub parameters: Vec<f64>,
+}
+
+/// Parameter that varies between agents and evolves, see [`HeritableTrait`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TraitConfig {
+    /// Names the output columns and the trait in `--set traits.<name>.<key>`.
+    pub name: String,
+    /// A hazard component ("aging", "learning", "growth" or an additional
+    /// one), "female_fertility", "male_fertility" or "neutral" for a trait
+    /// acting only through tradeoffs and mate choice.
+    pub component: String,
+    /// Name of the parameter in that component, e.g. "b" or "lmax".
+    pub parameter: String,
+    /// [mean, standard deviation] of the founders, the baseline value without variance when absent.
+    #[serde(default)]
+    pub initial_distribution: Option<[f64; 2]>,
+    /// "maternal", "paternal" or "midparent", for traits without loci.
+    #[serde(default = "default_trait_inheritance")]
+    pub inheritance: Inheritance,
+    #[serde(default)]
+    pub mutation_rate: f64,
+    #[serde(default)]
+    pub mutation_strength: f64,
+    #[serde(default)]
+    pub kernel: MutationKernel,
+    #[serde(default)]
+    pub mutation_bias: f64,
+    #[serde(default)]
+    pub lower_bound: f64,
+    #[serde(default = "default_upper_bound")]
+    pub upper_bound: f64,
+    #[serde(default)]
+    pub boundary: Boundary,
+    /// Number of diploid loci coding for the trait; without it the trait is
+    /// inherited according to `inheritance`.
+    #[serde(default)]
+    pub loci: Option<usize>,
+    /// Crossover probability between adjacent loci, 0.5 for free recombination.
+    #[serde(default = "default_recombination_rate")]
+    pub recombination_rate: f64,
+    #[serde(default)]
+    pub environmental_noise: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeoffShape {
+    Linear,
+    Power,
+    Saturating,
+    Threshold,
+}
+
+/// Relation between a heritable trait and fertility or survival, see
+/// [`TradeoffForm`] for the shapes. Only the keys of the chosen shape are used.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TradeoffConfig {
+    /// Name of a heritable trait.
+    #[serde(rename = "trait")]
+    pub trait_name: String,
+    pub target: TradeoffTarget,
+    pub form: TradeoffShape,
+    /// Trait value at which the factor is 1, the founders' mean when absent.
+    #[serde(default)]
+    pub reference: Option<f64>,
+    #[serde(default)]
+    pub intercept: f64,
+    #[serde(default = "default_one")]
+    pub slope: f64,
+    #[serde(default = "default_one")]
+    pub exponent: f64,
+    #[serde(default = "default_one")]
+    pub half_saturation: f64,
+    #[serde(default)]
+    pub threshold: f64,
+    #[serde(default)]
+    pub below: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimulationConfig {
+    // SIMULATION PARAMETERS
+    pub simulation_time: f64,
+    pub time_step: f64,
+    pub population_cap: usize,
+    pub initial_population: usize,
+    pub initial_female_proportion: f64,
+    #[serde(default = "default_replicate_number")]
+    pub replicate_number: usize,
+    /// Master seed of every random stream, drawn at startup when absent.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Number of replicates run concurrently, all available cores when absent.
+    #[serde(default)]
+    pub replicate_threads: Option<usize>,
+
+    pub initial_age_distribution: [f64; 2],
+
+    #[serde(default)]
+    pub assortative_mating: bool,
+    /// Random pairs every step when absent.
+    #[serde(default)]
+    pub mating: MatingParameters,
+    /// One offspring per successful couple and a sex ratio of 1/2 when absent.
+    #[serde(default)]
+    pub litter: LitterParameters,
+    #[serde(default)]
+    pub remove_non_reproducing: bool,
+    /// Density-dependent fertility and mortality, only the population cap when absent.
+    #[serde(default)]
+    pub density_regulation: Option<DensityRegulation>,
+
+    // GLA PARAMETERS
+    pub minimum_mortality: f64,
+    #[serde(default)]
+    pub death_probability: DeathProbability,
+    /// Family of the "aging" component; its senescence parameter is the evolving b.
+    #[serde(default = "default_aging_function")]
+    pub aging_function: HazardKind,
+    pub aging_parameters: Vec<f64>,
+    /// Leave out to drop the learning term.
+    #[serde(default)]
+    pub learning_parameters: Option<Vec<f64>>,
+    /// Leave out to drop the growth term.
+    #[serde(default)]
+    pub growth_parameters: Option<Vec<f64>>,
+    #[serde(default)]
+    pub hazard_components: Vec<HazardComponentConfig>,
+    /// Seasons, noise and catastrophes acting on a hazard parameter, a constant environment when absent.
+    #[serde(default)]
+    pub environment: Option<EnvironmentParameters>,
+
+    // FERTILITY PARAMETERS
+    pub female_fertility_function: FertilityFunction,
+    pub female_fertility_parameters: Vec<f64>,
+    pub male_fertility_function: FertilityFunction,
+    pub male_fertility_parameters: Vec<f64>,
+
+    // HERITABLE TRAITS
+    #[serde(default)]
+    pub traits: Vec<TraitConfig>,
+    #[serde(default)]
+    pub tradeoffs: Vec<TradeoffConfig>,
+    /// Mutation accumulation or antagonistic pleiotropy, none when absent.
+    #[serde(default)]
+    pub age_specific_mutations: Option<AgeSpecificMutationParameters>,
+    /// Inheritance of parameters that are not traits, by component then
+    /// parameter name; the others keep their baseline value.
+    #[serde(default)]
+    pub parameter_inheritance: BTreeMap<String, BTreeMap<String, Inheritance>>,
+
+    // GENEALOGY
+    #[serde(default)]
+    pub genealogy: GenealogyParameters,
+
+    // OUTPUT PARAMETERS
+    #[serde(default = "default_output_directory")]
+    pub output_directory: String,
+    #[serde(default = "default_base_name")]
+    pub base_name: String,
+    /// Steps between two checkpoints of a running replicate, none when absent.
+    #[serde(default)]
+    pub checkpoint_interval: Option<usize>,
+}
+
+fn default_replicate_number() -> usize {
+    1
+}
+
+fn default_aging_function() -> HazardKind {
+    HazardKind::GompertzMakeham
+}
+
+fn default_one() -> f64 {
+    1.0
+}
+
+fn default_trait_inheritance() -> Inheritance {
+    Inheritance::Midparent
+}
+
+fn default_recombination_rate() -> f64 {
+    0.5
+}
+
+fn default_upper_bound() -> f64 {
+    f64::INFINITY
+}
+
+fn default_output_directory() -> String {
+    "./simulation_results".to_string()
+}
+
+fn default_base_name() -> String {
+    "simulation".to_string()
+}
+
+impl SimulationConfig {
+    /// Reads a configuration file, replaces the given keys and validates the result.
+    ///
+    /// Keys are dotted paths; inside an array of tables a segment selects the
+    /// entry with that `name`, so `traits.b.mutation_rate` reaches the rate of
+    /// the trait named "b".
+    pub fn from_file_with_overrides<P: AsRef<Path>>(
+        path: P,
+        overrides: &[(String, toml::Value)],
+    ) -> Result<Self, ConfigError> {
+        let content = fs::read_to_string(path)?;
+        let mut table: toml::Table = toml::from_str(&content)?;
+        for (key, value) in overrides {
+            set_override(&mut table, key, value.clone())?;
+        }
+        let config: SimulationConfig = toml::Value::Table(table).try_into()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check(
+            self.time_step > 0.0 && self.time_step.is_finite(),
+            "time_step must be a positive number",
+        )?;
+        check(
+            self.simulation_time >= self.time_step,
+            "simulation_time must be at least one time_step",
+        )?;
+        check(self.population_cap > 0, "population_cap must be positive")?;
+        check(
+            self.initial_population <= self.population_cap,
+            "initial_population cannot exceed population_cap",
+        )?;
+        check(self.replicate_number > 0, "replicate_number must be positive")?;
+        check(
+            self.checkpoint_interval != Some(0),
+            "checkpoint_interval must be positive",
+        )?;
+        check(
+            self.replicate_threads != Some(0),
+            "replicate_threads must be positive",
+        )?;
+        check_probability(self.initial_female_proportion, "initial_female_proportion")?;
+
+        check_distribution(&self.initial_age_distribution, "initial_age_distribution")?;
+
+        check(self.minimum_mortality >= 0.0, "minimum_mortality must be non-negative")?;
+        check_length(
+            &self.aging_parameters,
+            self.aging_function.component().parameter_names().len(),
+            "aging_parameters",
+        )?;
+        if let Some(learning_parameters) = &self.learning_parameters {
+            check_length(learning_parameters, 3, "learning_parameters")?;
+        }
+        if let Some(growth_parameters) = &self.growth_parameters {
+            check_length(growth_parameters, 2, "growth_parameters")?;
+        }
+        for (i, component) in self.hazard_components.iter().enumerate() {
+            check(
+                !["aging", "learning", "growth"].contains(&component.name.as_str())
+                    && !self.hazard_components[..i]
+                        .iter()
+                        .any(|other| other.name == component.name),
+                &format!("hazard component name '{}' is already used", component.name),
+            )?;
+            check_length(
+                &component.parameters,
+                component.kind.component().parameter_names().len(),
+                &format!("parameters of hazard component '{}'", component.name),
+            )?;
+        }
+        check_length(
+            &self.female_fertility_parameters,
+            self.female_fertility_function.parameter_names().len(),
+            "female_fertility_parameters",
+        )?;
+        check_length(
+            &self.male_fertility_parameters,
+            self.male_fertility_function.parameter_names().len(),
+            "male_fertility_parameters",
+        )?;
+        for (function, parameters, name) in [
+            (self.female_fertility_function, &self.female_fertility_parameters, "female"),
+            (self.male_fertility_function, &self.male_fertility_parameters, "male"),
+        ] {
+            let (start, end) = function.support(parameters);
+            check(
+                start.is_finite() && start < end,
+                &format!("the {} reproductive window [{}, {}] is empty", name, start, end),
+            )?;
+            if let Err(e) = function.maximum(parameters) {
+                return Err(ConfigError::Invalid(format!("{} fertility: {}", name, e)));
+            }
+        }
+
+        for (i, trait_config) in self.traits.iter().enumerate() {
+            let name = format!("trait '{}'", trait_config.name);
+            check(
+                !self.traits[..i].iter().any(|other| other.name == trait_config.name),
+                &format!("{} is declared twice", name),
+            )?;
+            check(
+                self.trait_location(trait_config).is_some(),
+                &format!(
+                    "{}: '{}' has no parameter '{}'",
+                    name, trait_config.component, trait_config.parameter
+                ),
+            )?;
+            if self.trait_location(trait_config) == Some(TraitLocation::SexRatio) {
+                check(
+                    trait_config.upper_bound <= 1.0,
+                    &format!("{}: a sex ratio needs an upper_bound of at most 1", name),
+                )?;
+            }
+            if let Some(distribution) = &trait_config.initial_distribution {
+                check_distribution(distribution, &format!("initial_distribution of {}", name))?;
+            }
+            check_probability(trait_config.mutation_rate, &format!("mutation_rate of {}", name))?;
+            check(
+                trait_config.mutation_strength >= 0.0,
+                &format!("mutation_strength of {} must be non-negative", name),
+            )?;
+            check(
+                trait_config.lower_bound <= trait_config.upper_bound,
+                &format!("lower_bound of {} exceeds its upper_bound", name),
+            )?;
+            check(
+                trait_config.mutation_bias.is_finite(),
+                &format!("mutation_bias of {} must be a number", name),
+            )?;
+            check(
+                trait_config.inheritance != Inheritance::Baseline,
+                &format!("inheritance of {} must be maternal, paternal or midparent", name),
+            )?;
+            check(
+                trait_config.loci.is_none() || trait_config.inheritance == Inheritance::Midparent,
+                &format!("{} has loci and is inherited through its alleles", name),
+            )?;
+            check(trait_config.loci != Some(0), &format!("loci of {} must be positive", name))?;
+            check(
+                (0.0..=0.5).contains(&trait_config.recombination_rate),
+                &format!("recombination_rate of {} must lie in [0, 0.5]", name),
+            )?;
+            check(
+                trait_config.environmental_noise >= 0.0,
+                &format!("environmental_noise of {} must be non-negative", name),
+            )?;
+        }
+
+        check(
+            self.mating.encounter_rate >= 0.0 && self.mating.encounter_rate.is_finite(),
+            "mating.encounter_rate must be a non-negative number",
+        )?;
+        if let Some(choice) = &self.mating.choice {
+            check(choice.candidates > 0, "mating.choice.candidates must be positive")?;
+            if let Some([_, standard_deviation]) = choice.age_difference {
+                check(
+                    standard_deviation > 0.0,
+                    "mating.choice.age_difference must be a [mean, standard deviation] with a positive standard deviation",
+                )?;
+            }
+            for (key, trait_name, strength) in [
+                ("assortment", &choice.assortment_trait, choice.assortment_strength),
+                ("display", &choice.display_trait, choice.display_strength),
+            ] {
+                if let Some(trait_name) = trait_name {
+                    check(
+                        self.traits.iter().any(|trait_config| &trait_config.name == trait_name),
+                        &format!("mating.choice.{}_trait: no heritable trait is named '{}'", key, trait_name),
+                    )?;
+                }
+                check(strength.is_finite(), &format!("mating.choice.{}_strength must be a number", key))?;
+            }
+        }
+
+        match self.litter.distribution {
+            LitterSize::Fixed => check(
+                self.litter.size >= 0.0 && self.litter.size.fract() == 0.0,
+                "litter.size of a fixed litter must be a non-negative integer",
+            )?,
+            LitterSize::Poisson => check(
+                self.litter.size >= 0.0 && self.litter.size.is_finite(),
+                "litter.size must be a non-negative number",
+            )?,
+            LitterSize::AgeDependent => {
+                check(
+                    self.litter.age_classes.iter().all(|age| age.is_finite())
+                        && self.litter.age_classes.windows(2).all(|ages| ages[0] < ages[1]),
+                    "litter.age_classes must be increasing ages",
+                )?;
+                check_length(&self.litter.sizes, self.litter.number_of_classes(), "litter.sizes")?;
+                check(
+                    self.litter.sizes.iter().all(|size| *size >= 0.0 && size.is_finite()),
+                    "litter.sizes must be non-negative numbers",
+                )?;
+            }
+        }
+        check_probability(self.litter.sex_ratio, "litter.sex_ratio")?;
+
+        if let Some(regulation) = &self.density_regulation {
+            check(
+                regulation.carrying_capacity > 0.0 && regulation.carrying_capacity.is_finite(),
+                "density_regulation.carrying_capacity must be a positive number",
+            )?;
+            check(
+                regulation.mortality >= 0.0 && regulation.mortality.is_finite(),
+                "density_regulation.mortality must be a non-negative number",
+            )?;
+            check(
+                regulation.age_classes.iter().all(|age| age.is_finite())
+                    && regulation.age_classes.windows(2).all(|ages| ages[0] < ages[1]),
+                "density_regulation.age_classes must be increasing ages",
+            )?;
+            if let Some(competition) = &regulation.competition {
+                let classes = regulation.number_of_classes();
+                check(
+                    competition.len() == classes && competition.iter().all(|row| row.len() == classes),
+                    &format!("density_regulation.competition must be a {0}x{0} matrix, one row per age class", classes),
+                )?;
+                check(
+                    competition.iter().flatten().all(|effect| *effect >= 0.0 && effect.is_finite()),
+                    "density_regulation.competition coefficients must be non-negative",
+                )?;
+            }
+        }
+
+        if let Some(environment) = &self.environment {
+            check(
+                matches!(
+                    self.parameter_location(&environment.component, &environment.parameter),
+                    Some(TraitLocation::Hazard { .. })
+                ),
+                &format!(
+                    "environment: hazard component '{}' has no parameter '{}'",
+                    environment.component, environment.parameter
+                ),
+            )?;
+            check(
+                (0.0..=1.0).contains(&environment.seasonal_amplitude),
+                "environment.seasonal_amplitude must lie in [0, 1]",
+            )?;
+            check(
+                environment.seasonal_amplitude == 0.0 || environment.seasonal_period.is_some(),
+                "environment.seasonal_period is required with a seasonal_amplitude",
+            )?;
+            if let Some(period) = environment.seasonal_period {
+                // Steps sample a period of two steps or less only at aliased phases,
+                // e.g. at the zeros of the sine for a period of one step.
+                check(
+                    period > 2.0 * self.time_step && period.is_finite(),
+                    &format!(
+                        "environment.seasonal_period must be longer than two time steps, {}",
+                        2.0 * self.time_step
+                    ),
+                )?;
+            }
+            check(
+                environment.noise_autocorrelation > -1.0 && environment.noise_autocorrelation < 1.0,
+                "environment.noise_autocorrelation must lie in (-1, 1)",
+            )?;
+            check(
+                environment.noise_strength >= 0.0 && environment.noise_strength.is_finite(),
+                "environment.noise_strength must be a non-negative number",
+            )?;
+            check(
+                environment.catastrophe_rate >= 0.0 && environment.catastrophe_rate.is_finite(),
+                "environment.catastrophe_rate must be a non-negative number",
+            )?;
+            check_probability(environment.catastrophe_severity, "environment.catastrophe_severity")?;
+        }
+
+        for (component, parameters) in &self.parameter_inheritance {
+            for parameter in parameters.keys() {
+                let name = format!("parameter_inheritance of '{}.{}'", component, parameter);
+                let location = self.parameter_location(component, parameter);
+                check(location.is_some(), &format!("{}: no such parameter", name))?;
+                check(
+                    !self.traits.iter().any(|trait_config| self.trait_location(trait_config) == location),
+                    &format!("{}: the parameter is a heritable trait, set the inheritance of the trait", name),
+                )?;
+            }
+        }
+
+        let traits = self.heritable_traits();
+        for tradeoff in &self.tradeoffs {
+            let name = format!("tradeoff on trait '{}'", tradeoff.trait_name);
+            let heritable_trait = traits.iter().find(|heritable_trait| heritable_trait.name == tradeoff.trait_name);
+            check(heritable_trait.is_some(), &format!("{}: no heritable trait has this name", name))?;
+            // Without a reference, the trait's initial mean is the reference.
+            let reference = tradeoff
+                .reference
+                .or(heritable_trait.map(|heritable_trait| heritable_trait.initial_distribution[0]));
+            check(
+                reference.is_some_and(|reference| reference != 0.0 && reference.is_finite()),
+                &format!(
+                    "reference of {} must be a non-zero number, set it when the initial mean of the trait is 0",
+                    name
+                ),
+            )?;
+            check(
+                tradeoff.half_saturation > 0.0,
+                &format!("half_saturation of {} must be positive", name),
+            )?;
+        }
+
+        if let Some(mutations) = &self.age_specific_mutations {
+            check(
+                mutations.rate >= 0.0 && mutations.rate.is_finite(),
+                "age_specific_mutations.rate must be non-negative",
+            )?;
+            check(
+                0.0 <= mutations.onset_range[0] && mutations.onset_range[0] <= mutations.onset_range[1],
+                "age_specific_mutations.onset_range must be an increasing range of ages",
+            )?;
+            check(
+                mutations.hazard_effect >= 0.0 && mutations.window >= 0.0,
+                "age_specific_mutations.hazard_effect and window must be non-negative",
+            )?;
+            check(
+                mutations.fertility_effect > -1.0,
+                "age_specific_mutations.fertility_effect must exceed -1",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// GLA components ("aging", then "learning" and "growth" when present)
+    /// followed by the additional components, in configuration order.
+    pub fn hazard_model(&self) -> HazardModel {
+        let mut model = HazardModel::new(self.minimum_mortality).with_component(
+            "aging",
+            self.aging_function.component(),
+            self.aging_parameters.clone(),
+        );
+        if let Some(learning_parameters) = &self.learning_parameters {
+            model = model.with_component("learning", Box::new(Learning), learning_parameters.clone());
+        }
+        if let Some(growth_parameters) = &self.growth_parameters {
+            model = model.with_component("growth", Box::new(Growth), growth_parameters.clone());
+        }
+        for component in &self.hazard_components {
+            model = model.with_component(
+                &component.name,
+                component.kind.component(),
+                component.parameters.clone(),
+            );
+        }
+        model
+    }
+
+    /// Where the parameter of a trait lives in the agents, `None` when there
+    /// is no such component or parameter.
+    pub fn trait_location(&self, trait_config: &TraitConfig) -> Option<TraitLocation> {
+        self.parameter_location(&trait_config.component, &trait_config.parameter)
+    }
+
+    /// Where `parameter` of `component`, a hazard component, a fertility
+    /// schedule, "neutral" or "litter", lives in the agents.
+    pub fn parameter_location(&self, component: &str, parameter: &str) -> Option<TraitLocation> {
+        let parameter_index = |names: &[&str]| names.iter().position(|name| *name == parameter);
+        match component {
+            "litter" => (parameter == "sex_ratio").then_some(TraitLocation::SexRatio),
+            "neutral" => parameter_index(&self.neutral_parameter_names()).map(|index| TraitLocation::Neutral { index }),
+            "female_fertility" => parameter_index(self.female_fertility_function.parameter_names())
+                .map(|index| TraitLocation::Fertility { female: true, index }),
+            "male_fertility" => parameter_index(self.male_fertility_function.parameter_names())
+                .map(|index| TraitLocation::Fertility { female: false, index }),
+            name => {
+                let model = self.hazard_model();
+                let component = model.component_index(name)?;
+                parameter_index(model.component(component).parameter_names())
+                    .map(|index| TraitLocation::Hazard { component, index })
+            }
+        }
+    }
+
+    fn baseline_value(&self, location: TraitLocation) -> f64 {
+        match location {
+            TraitLocation::Hazard { component, index } => self.hazard_model().baseline_parameters()[component][index],
+            TraitLocation::Fertility { female: true, index } => self.female_fertility_parameters[index],
+            TraitLocation::Fertility { female: false, index } => self.male_fertility_parameters[index],
+            TraitLocation::Neutral { .. } => 0.0,
+            TraitLocation::SexRatio => self.litter.sex_ratio,
+        }
+    }
+
+    /// Parameters of the neutral traits, in declaration order. Their baseline value is 0.
+    pub fn neutral_parameter_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for trait_config in self.traits.iter().filter(|trait_config| trait_config.component == "neutral") {
+            if !names.contains(&trait_config.parameter.as_str()) {
+                names.push(&trait_config.parameter);
+            }
+        }
+        names
+    }
+
+    /// Heritable traits in declaration order, which is also the order of
+    /// their output columns.
+    pub fn heritable_traits(&self) -> Vec<HeritableTrait> {
+        self.traits
+            .iter()
+            .map(|trait_config| {
+                let location = self
+                    .trait_location(trait_config)
+                    .expect("trait locations are checked by validate");
+                HeritableTrait {
+                    name: trait_config.name.clone(),
+                    location,
+                    initial_distribution: trait_config
+                        .initial_distribution
+                        .unwrap_or([self.baseline_value(location), 0.0]),
+                    inheritance: trait_config.inheritance,
+                    mutation_rate: trait_config.mutation_rate,
+                    mutation_strength: trait_config.mutation_strength,
+                    kernel: trait_config.kernel,
+                    mutation_bias: trait_config.mutation_bias,
+                    bounds: [trait_config.lower_bound, trait_config.upper_bound],
+                    boundary: trait_config.boundary,
+                    genetics: trait_config.loci.map(|loci| Genetics {
+                        loci,
+                        recombination_rate: trait_config.recombination_rate,
+                        environmental_noise: trait_config.environmental_noise,
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// Mean founder value of a parameter: the initial mean of the trait that
+    /// evolves it, or its baseline value.
+    fn initial_value(&self, location: TraitLocation) -> f64 {
+        self.heritable_traits()
+            .iter()
+            .find(|heritable_trait| heritable_trait.location == location)
+            .map_or_else(|| self.baseline_value(location), |heritable_trait| heritable_trait.initial_distribution[0])
+    }
+
+    pub fn environment(&self) -> Option<Environment> {
+        self.environment.as_ref().map(|environment| {
+            let Some(TraitLocation::Hazard { component, index }) =
+                self.parameter_location(&environment.component, &environment.parameter)
+            else {
+                panic!("the environment parameter is checked by validate");
+            };
+            Environment {
+                component,
+                index,
+                seasonal_amplitude: environment.seasonal_amplitude,
+                seasonal_period: environment.seasonal_period.unwrap_or(f64::INFINITY),
+                noise_autocorrelation: environment.noise_autocorrelation,
+                noise_strength: environment.noise_strength,
+                catastrophe_rate: environment.catastrophe_rate,
+                catastrophe_severity: environment.catastrophe_severity,
+            }
+        })
+    }
+
+    /// Female mate choice with its traits resolved, when configured.
+    pub fn mate_choice(&self) -> Option<MateChoice> {
+        let traits = self.heritable_traits();
+        let preference = |trait_name: &Option<String>, strength: f64| {
+            trait_name.as_ref().map(|trait_name| TraitPreference {
+                location: traits
+                    .iter()
+                    .find(|heritable_trait| &heritable_trait.name == trait_name)
+                    .expect("mate choice traits are checked by validate")
+                    .location,
+                strength,
+            })
+        };
+        self.mating.choice.as_ref().map(|choice| MateChoice {
+            candidates: choice.candidates,
+            age_difference: choice.age_difference,
+            assortment: preference(&choice.assortment_trait, choice.assortment_strength),
+            display: preference(&choice.display_trait, choice.display_strength),
+        })
+    }
+
+    /// Baseline values of the parameters with the configured inheritance.
+    pub fn offspring_template(&self) -> OffspringTemplate {
+        let mut template = OffspringTemplate::baseline(
+            &self.hazard_model().baseline_parameters(),
+            &self.female_fertility_parameters,
+            &self.male_fertility_parameters,
+            &vec![0.0; self.neutral_parameter_names().len()],
+            self.litter.sex_ratio,
+        );
+        for (component, parameters) in &self.parameter_inheritance {
+            for (parameter, inheritance) in parameters {
+                let location = self
+                    .parameter_location(component, parameter)
+                    .expect("parameter_inheritance is checked by validate");
+                template = template.with_inheritance(location, *inheritance);
+            }
+        }
+        template
+    }
+
+    pub fn number_of_steps(&self) -> usize {
+        (self.simulation_time / self.time_step).round() as usize
+    }
+
+    /// Tradeoffs acting on `target`, in configuration order.
+    pub fn tradeoffs(&self, target: TradeoffTarget) -> Vec<Tradeoff> {
+        let traits = self.heritable_traits();
+        self.tradeoffs
+            .iter()
+            .filter(|tradeoff| tradeoff.target == target)
+            .map(|tradeoff| {
+                let heritable_trait = traits
+                    .iter()
+                    .find(|heritable_trait| heritable_trait.name == tradeoff.trait_name)
+                    .expect("tradeoff traits are checked by validate");
+                let form = match tradeoff.form {
+                    TradeoffShape::Linear => TradeoffForm::Linear {
+                        intercept: tradeoff.intercept,
+                        slope: tradeoff.slope,
+                    },
+                    TradeoffShape::Power => TradeoffForm::Power { exponent: tradeoff.exponent },
+                    TradeoffShape::Saturating => TradeoffForm::Saturating {
+                        half_saturation: tradeoff.half_saturation,
+                    },
+                    TradeoffShape::Threshold => TradeoffForm::Threshold {
+                        threshold: tradeoff.threshold,
+                        below: tradeoff.below,
+                    },
+                };
+                Tradeoff {
+                    location: heritable_trait.location,
+                    reference: tradeoff.reference.unwrap_or(heritable_trait.initial_distribution[0]),
+                    target,
+                    form,
+                }
+            })
+            .collect()
+    }
+
+    pub fn output_file_path(&self) -> String {
+        let learning_name_part = if self.learning_parameters.is_some() { "with_learning" } else { "no_learning" };
+        let pairing_name_part = if self.assortative_mating { "assortative" } else { "random" };
+        let system_name_part = match self.mating.system {
+            MatingSystem::RandomPairs => "mating",
+            MatingSystem::Monogamy => "monogamy",
+            MatingSystem::Polygyny => "polygyny",
+            MatingSystem::Promiscuous => "promiscuous",
+        };
+        let choice_name_part = if self.mating.choice.is_some() { "_mate_choice" } else { "" };
+        let mating_name_part = format!("{}_{}{}", pairing_name_part, system_name_part, choice_name_part);
+        let removal_name_part = if self.remove_non_reproducing { "non_reproducing_removed" } else { "non_reproducing_kept" };
+        let tradeoff_name_part = if self.tradeoffs.is_empty() { "no_tradeoff" } else { "tradeoff" };
+        let model = self.hazard_model();
+        let initial_lmax = model.component_index("learning").map_or(0.0, |component| {
+            self.initial_value(TraitLocation::Hazard { component, index: 0 })
+        });
+        format!(
+            "{}/{}_{}_{}_{}_{}_{}.csv",
+            self.output_directory,
+            self.base_name,
+            mating_name_part,
+            learning_name_part,
+            removal_name_part,
+            tradeoff_name_part,
+            initial_lmax
+        )
+    }
+
+    /// Directory holding the checkpoints of the replicates written to `output_file_path`.
+    pub fn checkpoint_directory(&self) -> String {
+        let output_file_path = self.output_file_path();
+        let stem = output_file_path.strip_suffix(".csv").unwrap_or(&output_file_path);
+        format!("{}_checkpoints", stem)
+    }
+
+    /// File keeping the master seed drawn for a configuration without one, so
+    /// that a resumed run continues the same random streams.
+    pub fn seed_file_path(&self) -> String {
+        let output_file_path = self.output_file_path();
+        let stem = output_file_path.strip_suffix(".csv").unwrap_or(&output_file_path);
+        format!("{}_seed.txt", stem)
+    }
+
+    /// Directory holding the pedigree files of the replicates written to `output_file_path`.
+    pub fn pedigree_directory(&self) -> String {
+        let output_file_path = self.output_file_path();
+        let stem = output_file_path.strip_suffix(".csv").unwrap_or(&output_file_path);
+        format!("{}_pedigree", stem)
+    }
+
+    /// Maximum of the female schedule, which normalizes it into a probability.
+    /// Validation guarantees that it exists.
+    pub fn female_maximum_fertility(&self) -> FertilityMaximum {
+        self.female_fertility_function
+            .maximum(&self.female_fertility_parameters)
+            .expect("female fertility maximum is checked by validate")
+    }
+
+    pub fn male_maximum_fertility(&self) -> FertilityMaximum {
+        self.male_fertility_function
+            .maximum(&self.male_fertility_parameters)
+            .expect("male fertility maximum is checked by validate")
+    }
+}
+
+/// Replaces the value at a dotted `key`, see [`SimulationConfig::from_file_with_overrides`].
+fn set_override(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<(), ConfigError> {
+    let unknown = || ConfigError::Invalid(format!("cannot override '{}': no such entry", key));
+    let mut segments = key.split('.').collect::<Vec<_>>();
+    let last = segments.pop().unwrap_or(key);
+
+    let mut current = table;
+    let mut segments = segments.into_iter();
+    while let Some(segment) = segments.next() {
+        current = match current.get_mut(segment).ok_or_else(unknown)? {
+            toml::Value::Table(child) => child,
+            toml::Value::Array(entries) => {
+                let name = segments.next().ok_or_else(unknown)?;
+                entries
+                    .iter_mut()
+                    .find_map(|entry| match entry {
+                        toml::Value::Table(child) if child.get("name").and_then(toml::Value::as_str) == Some(name) => {
+                            Some(child)
+                        }
+                        _ => None,
+                    })
+                    .ok_or_else(unknown)?
+            }
+            _ => return Err(unknown()),
+        };
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+fn check(condition: bool, message: &str) -> Result<(), ConfigError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(message.to_string()))
+    }
+}
+
+fn check_probability(value: f64, name: &str) -> Result<(), ConfigError> {
+    check(
+        (0.0..=1.0).contains(&value),
+        &format!("{} must be between 0 and 1", name),
+    )
+}
+
+fn check_distribution(distribution: &[f64; 2], name: &str) -> Result<(), ConfigError> {
+    check(
+        distribution[0].is_finite() && distribution[1].is_finite() && distribution[1] >= 0.0,
+        &format!("{} must be [mean, standard deviation] with a non-negative deviation", name),
+    )
+}
+
+fn check_length(parameters: &[f64], expected: usize, name: &str) -> Result<(), ConfigError> {
+    check(
+        parameters.len() == expected,
+        &format!(
+            "{} expects {} values, got {}",
+            name,
+            expected,
+            parameters.len()
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gla_package::gla::find_maximum_fertility;
+
+    #[test]
+    fn closed_form_maxima_match_numeric_search() {
+        let cases: [(FertilityFunction, &[f64]); 6] = [
+            (FertilityFunction::BrassPolynomial, &[2.445e-5, 14.8, 32.836]),
+            (FertilityFunction::Hadwiger, &[1.8, 3.5, 28.0]),
+            (FertilityFunction::Hadwiger, &[2.0, 0.9, 25.0]),
+            (FertilityFunction::Gamma, &[2.0, 15.0, 4.0, 3.5]),
+            (FertilityFunction::CoaleTrussell, &[1.0, 0.0]),
+            (FertilityFunction::CoaleTrussell, &[1.0, 1.5]),
+        ];
+        for (family, parameters) in cases {
+            let closed_form = family.maximum(parameters).unwrap();
+            let numeric = find_maximum_fertility(&family.function(), parameters, family.support(parameters)).unwrap();
+            let context = format!("{:?} {:?}", family, parameters);
+            assert!(
+                (closed_form.value - numeric.value).abs() <= 1e-9 * numeric.value,
+                "{}: {:?} vs {:?}",
+                context,
+                closed_form,
+                numeric
+            );
+            let value_at_age = family.function()(closed_form.age, parameters);
+            assert!((closed_form.value - value_at_age).abs() <= 1e-12 * value_at_age, "{}", context);
+            if family != FertilityFunction::CoaleTrussell {
+                // Coale-Trussell is flat within an age group.
+                assert!((closed_form.age - numeric.age).abs() < 1e-3, "{}: {:?} vs {:?}", context, closed_form, numeric);
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::iter::zip;
+
+use serde::Deserialize;
+
+use crate::gla_package::agent_based::Agent;
+
+/// How the probability of reproducing falls with density.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DensityFertility {
+    /// Density leaves fertility unchanged.
+    #[default]
+    None,
+    /// `1 / (1 + N / K)`, halved at the carrying capacity.
+    BevertonHolt,
+    /// `exp(-N / K)`, overcompensating at high density.
+    Ricker,
+}
+
+/// Regulation experienced by a population, averaged over its agents.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DensityStats {
+    pub density: f64,
+    /// Over the females only, on whom it acts.
+    pub fertility_factor: f64,
+    pub hazard: f64,
+}
+
+/// Regulation of the population through density-dependent fertility and
+/// mortality, on top of the hard `population_cap`.
+///
+/// The density `N` an agent feels is the number of agents weighted by the
+/// competition between age classes: with classes split at `age_classes`, an
+/// agent of class i feels `sum_j competition[i][j] N_j`. Without classes,
+/// every agent feels the population size.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DensityRegulation {
+    /// Density scale `K` of the fertility and mortality responses.
+    pub carrying_capacity: f64,
+    #[serde(default)]
+    pub fertility: DensityFertility,
+    /// Extrinsic hazard added at density `K`, proportional to the density.
+    #[serde(default)]
+    pub mortality: f64,
+    /// Ages at which a new competition class starts, in increasing order.
+    #[serde(default)]
+    pub age_classes: Vec<f64>,
+    /// Effect of one agent of class j on the density felt by class i, in row
+    /// i; every coefficient is 1 when absent.
+    #[serde(default)]
+    pub competition: Option<Vec<Vec<f64>>>,
+}
+
+impl DensityRegulation {
+    pub fn number_of_classes(&self) -> usize {
+        self.age_classes.len() + 1
+    }
+
+    pub fn age_class(&self, age: f64) -> usize {
+        self.age_classes.iter().take_while(|&&start| age >= start).count()
+    }
+
+    /// Density felt by each age class.
+    pub fn class_densities(&self, population: &[Agent]) -> Vec<f64> {
+        let mut class_sizes = vec![0.0; self.number_of_classes()];
+        for agent in population {
+            class_sizes[self.age_class(agent.age)] += 1.0;
+        }
+        match &self.competition {
+            Some(competition) => competition
+                .iter()
+                .map(|row| row.iter().zip(&class_sizes).map(|(effect, size)| effect * size).sum())
+                .collect(),
+            None => vec![population.len() as f64; self.number_of_classes()],
+        }
+    }
+
+    /// Density felt by `agent` given the densities of the classes.
+    pub fn density(&self, class_densities: &[f64], agent: &Agent) -> f64 {
+        class_densities[self.age_class(agent.age)]
+    }
+
+    /// Factor applied to the probability of reproducing.
+    pub fn fertility_factor(&self, density: f64) -> f64 {
+        match self.fertility {
+            DensityFertility::None => 1.0,
+            DensityFertility::BevertonHolt => 1.0 / (1.0 + density / self.carrying_capacity),
+            DensityFertility::Ricker => (-density / self.carrying_capacity).exp(),
+        }
+    }
+
+    /// Hazard added to the agents' own.
+    pub fn hazard(&self, density: f64) -> f64 {
+        self.mortality * density / self.carrying_capacity
+    }
+
+    pub fn stats(&self, population: &[Agent]) -> DensityStats {
+        let class_densities = self.class_densities(population);
+        let densities = population
+            .iter()
+            .map(|agent| self.density(&class_densities, agent))
+            .collect::<Vec<_>>();
+        let female_factors = zip(population, &densities)
+            .filter(|(agent, _)| agent.female)
+            .map(|(_, &density)| self.fertility_factor(density))
+            .collect::<Vec<_>>();
+        let hazards = densities.iter().map(|&density| self.hazard(density)).collect::<Vec<_>>();
+        DensityStats {
+            density: mean(&densities),
+            fertility_factor: mean(&female_factors),
+            hazard: mean(&hazards),
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
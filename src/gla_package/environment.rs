@@ -0,0 +1,113 @@
+use std::f64::consts::PI;
+
+use rand::seq::index;
+use rand::Rng;
+use rand_distr::{Distribution, StandardNormal};
+use serde::Deserialize;
+
+use crate::gla_package::agent_based::Agent;
+
+/// Environmental processes driving a hazard parameter, as read from the configuration.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvironmentParameters {
+    /// Hazard component and parameter scaled by the environment, the Makeham
+    /// term by default.
+    #[serde(default = "default_component")]
+    pub component: String,
+    #[serde(default = "default_parameter")]
+    pub parameter: String,
+    #[serde(default)]
+    pub seasonal_amplitude: f64,
+    /// Required with seasons, longer than two time steps.
+    #[serde(default)]
+    pub seasonal_period: Option<f64>,
+    #[serde(default)]
+    pub noise_autocorrelation: f64,
+    #[serde(default)]
+    pub noise_strength: f64,
+    #[serde(default)]
+    pub catastrophe_rate: f64,
+    #[serde(default)]
+    pub catastrophe_severity: f64,
+}
+
+fn default_component() -> String {
+    "aging".to_string()
+}
+
+fn default_parameter() -> String {
+    "c".to_string()
+}
+
+/// Time-varying environment. The parameter at `component`, `index` of every
+/// agent is multiplied during a step by
+/// `(1 + seasonal_amplitude sin(2 pi t / seasonal_period)) exp(x_t)`, where
+/// `x_t` follows the AR(1) process `x_t = noise_autocorrelation x_{t-1} +
+/// noise_strength e_t`. Catastrophes strike at `catastrophe_rate` per unit of
+/// time and kill `catastrophe_severity` of the population.
+#[derive(Clone, Debug)]
+pub struct Environment {
+    pub component: usize,
+    pub index: usize,
+    pub seasonal_amplitude: f64,
+    /// Infinite without seasons.
+    pub seasonal_period: f64,
+    pub noise_autocorrelation: f64,
+    pub noise_strength: f64,
+    pub catastrophe_rate: f64,
+    pub catastrophe_severity: f64,
+}
+
+/// A hazard parameter scaled during a step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HazardScaling {
+    pub component: usize,
+    pub index: usize,
+    pub factor: f64,
+}
+
+/// State of the environment during the last step.
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+pub struct EnvironmentStats {
+    pub factor: f64,
+    pub catastrophe_deaths: usize,
+}
+
+impl Environment {
+    /// Next value of the AR(1) noise.
+    pub fn next_noise(&self, noise: f64, rng: &mut impl Rng) -> f64 {
+        if self.noise_strength > 0.0 {
+            let innovation: f64 = StandardNormal.sample(rng);
+            self.noise_autocorrelation * noise + self.noise_strength * innovation
+        } else {
+            noise
+        }
+    }
+
+    pub fn scaling(&self, time: f64, noise: f64) -> HazardScaling {
+        let season = 1.0 + self.seasonal_amplitude * (2.0 * PI * time / self.seasonal_period).sin();
+        HazardScaling {
+            component: self.component,
+            index: self.index,
+            factor: season * noise.exp(),
+        }
+    }
+
+    /// Whether a catastrophe strikes during a step.
+    pub fn catastrophe(&self, time_step: f64, rng: &mut impl Rng) -> bool {
+        self.catastrophe_rate > 0.0 && rng.gen::<f64>() < 1.0 - (-self.catastrophe_rate * time_step).exp()
+    }
+
+    /// Kills `catastrophe_severity` of the population, chosen at random, and
+    /// returns the number of deaths.
+    pub fn kill(&self, population: &mut Vec<Agent>, rng: &mut impl Rng) -> usize {
+        let deaths = (self.catastrophe_severity * population.len() as f64).round() as usize;
+        let mut dead_agent_indexes = index::sample(rng, population.len(), deaths).into_vec();
+        dead_agent_indexes.sort_unstable_by(|a, b| b.cmp(a));
+        for index in dead_agent_indexes {
+            population.swap_remove(index);
+        }
+        deaths
+    }
+}
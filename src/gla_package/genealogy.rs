@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom};
+use std::path::Path;
+
+use csv::{Writer, WriterBuilder};
+use serde::Deserialize;
+
+use crate::gla_package::agent_based::Agent;
+
+/// What is recorded about the ancestry of the agents.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenealogyParameters {
+    /// Writes every agent, founders included, to one pedigree file per replicate.
+    #[serde(default)]
+    pub pedigree_log: bool,
+    /// Follows the maternal lines of the living agents to report the founder
+    /// lineages still present and the time to their most recent common
+    /// ancestor. Both statistics ignore descent through fathers; the pedigree
+    /// log has both parents.
+    #[serde(default)]
+    pub track_lineages: bool,
+}
+
+/// Ancestry of the living population along maternal lines, as for
+/// mitochondrial lineages. The pedigree log keeps both parents.
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Genealogy {
+    /// The living agents and their maternal ancestors, by id.
+    nodes: BTreeMap<u64, Lineage>,
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+struct Lineage {
+    mother: Option<u64>,
+    birth_time: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LineageStats {
+    /// Founders with descendants alive through an unbroken line of mothers.
+    /// A founder whose living descendants all descend from a son is not
+    /// counted; a living founder is a lineage of its own.
+    pub maternal_founder_lineages: usize,
+    /// Time since the birth of the most recent common maternal ancestor of
+    /// the living agents, none while their maternal lines lead to several
+    /// founders.
+    pub maternal_tmrca: Option<f64>,
+}
+
+impl Genealogy {
+    pub fn new(population: &[Agent]) -> Self {
+        let mut genealogy = Genealogy::default();
+        genealogy.add(population);
+        genealogy
+    }
+
+    pub fn add(&mut self, agents: &[Agent]) {
+        for agent in agents {
+            self.nodes.insert(
+                agent.id,
+                Lineage {
+                    mother: agent.mother,
+                    birth_time: agent.birth_time,
+                },
+            );
+        }
+    }
+
+    /// Traces the maternal lines of `living` back until they merge or reach
+    /// the founders, then forgets the agents off those lines: those without
+    /// living descendants and the ancestors of the common ancestor.
+    ///
+    /// Ids grow with birth order, so walking back from the largest id always
+    /// reaches a daughter before her mother.
+    pub fn update(&mut self, living: &[Agent], time: f64) -> LineageStats {
+        let mut lineages: BTreeSet<u64> = living.iter().map(|agent| agent.id).collect();
+        let mut ancestors = BTreeSet::new();
+        let mut founders = BTreeSet::new();
+        let mut common_ancestor = None;
+        while let Some(id) = lineages.pop_last() {
+            ancestors.insert(id);
+            if lineages.is_empty() && founders.is_empty() {
+                common_ancestor = Some(id);
+                break;
+            }
+            match self.nodes[&id].mother {
+                Some(mother) => {
+                    lineages.insert(mother);
+                }
+                None => {
+                    founders.insert(id);
+                }
+            }
+        }
+        self.nodes.retain(|id, _| ancestors.contains(id));
+
+        match common_ancestor {
+            Some(id) => LineageStats {
+                maternal_founder_lineages: 1,
+                maternal_tmrca: Some(time - self.nodes[&id].birth_time),
+            },
+            None => LineageStats {
+                maternal_founder_lineages: founders.len(),
+                maternal_tmrca: None,
+            },
+        }
+    }
+}
+
+/// One row of a pedigree file.
+#[derive(serde::Serialize)]
+struct PedigreeRecord {
+    id: u64,
+    mother: Option<u64>,
+    father: Option<u64>,
+    birth_time: f64,
+    female: bool,
+}
+
+/// Pedigree file of one replicate, appended to as agents are born.
+pub struct PedigreeLog {
+    writer: Writer<File>,
+}
+
+impl PedigreeLog {
+    /// Starts a new file, replacing any previous one.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(PedigreeLog {
+            writer: Writer::from_path(path)?,
+        })
+    }
+
+    /// Continues a file from a checkpoint, dropping the rows written after it.
+    pub fn resume<P: AsRef<Path>>(path: P, length: u64) -> io::Result<Self> {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(length)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(PedigreeLog {
+            writer: WriterBuilder::new().has_headers(false).from_writer(file),
+        })
+    }
+
+    pub fn record(&mut self, agents: &[Agent]) -> io::Result<()> {
+        for agent in agents {
+            self.writer.serialize(PedigreeRecord {
+                id: agent.id,
+                mother: agent.mother,
+                father: agent.father,
+                birth_time: agent.birth_time,
+                female: agent.female,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the rows written so far and returns the length of the file,
+    /// which a checkpoint stores to resume from.
+    pub fn flush(&mut self) -> io::Result<u64> {
+        self.writer.flush()?;
+        Ok(self.writer.get_ref().metadata()?.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agent(id: u64, mother: Option<u64>, birth_time: f64) -> Agent {
+        Agent {
+            id,
+            mother,
+            father: None,
+            partner: None,
+            birth_time,
+            age: 0.0,
+            female: true,
+            hazard_parameters: Vec::new(),
+            female_fertility_parameters: Vec::new(),
+            male_fertility_parameters: Vec::new(),
+            neutral_parameters: Vec::new(),
+            sex_ratio: 0.5,
+            genome: Vec::new(),
+            age_specific_mutations: Vec::new(),
+        }
+    }
+
+    /// Founders 0 and 1; 2 is a daughter of 0, 3 and 4 daughters of 2 and 5
+    /// a daughter of 1.
+    fn pedigree() -> Vec<Agent> {
+        vec![
+            agent(0, None, -10.0),
+            agent(1, None, -5.0),
+            agent(2, Some(0), 1.0),
+            agent(3, Some(2), 3.0),
+            agent(4, Some(2), 4.0),
+            agent(5, Some(1), 6.0),
+        ]
+    }
+
+    fn lineages(living: &[u64]) -> LineageStats {
+        let agents = pedigree();
+        let mut genealogy = Genealogy::new(&agents);
+        let living: Vec<Agent> = agents.into_iter().filter(|agent| living.contains(&agent.id)).collect();
+        genealogy.update(&living, 10.0)
+    }
+
+    #[test]
+    fn maternal_lines_coalesce_or_lead_to_the_founders() {
+        // Sisters coalesce in their mother, who is not a founder.
+        assert_eq!(
+            lineages(&[3, 4]),
+            LineageStats { maternal_founder_lineages: 1, maternal_tmrca: Some(9.0) }
+        );
+        // Lines leading to two founders have no common ancestor.
+        assert_eq!(
+            lineages(&[3, 5]),
+            LineageStats { maternal_founder_lineages: 2, maternal_tmrca: None }
+        );
+        // A living founder is the common ancestor of her descendants...
+        assert_eq!(
+            lineages(&[0, 4]),
+            LineageStats { maternal_founder_lineages: 1, maternal_tmrca: Some(20.0) }
+        );
+        // ...and a lineage of her own next to another founder's.
+        assert_eq!(
+            lineages(&[1, 3]),
+            LineageStats { maternal_founder_lineages: 2, maternal_tmrca: None }
+        );
+    }
+
+    #[test]
+    fn update_keeps_only_the_lines_up_to_the_common_ancestor() {
+        let agents = pedigree();
+        let mut genealogy = Genealogy::new(&agents);
+        genealogy.update(&agents[3..5], 10.0);
+        // Founder 0 lies above the common ancestor 2 and is no longer needed.
+        assert_eq!(genealogy.nodes.keys().copied().collect::<Vec<_>>(), vec![2, 3, 4]);
+        // The pruned genealogy still traces the newborns of the survivors.
+        let newborn = agent(6, Some(4), 12.0);
+        genealogy.add(std::slice::from_ref(&newborn));
+        assert_eq!(
+            genealogy.update(&[agents[3].clone(), newborn], 15.0),
+            LineageStats { maternal_founder_lineages: 1, maternal_tmrca: Some(14.0) }
+        );
+    }
+}
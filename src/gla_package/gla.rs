@@ -1,7 +1,6 @@
-use ndarray::{Array, ArrayView1};
-use optimize::{Minimizer, NelderMeadBuilder};
+use peroxide::special::function::ln_gamma;
 
-pub fn _aging_gompertz(x: f64, aging_parameters: &[f64]) -> f64 {
+pub fn aging_gompertz(x: f64, aging_parameters: &[f64]) -> f64 {
     let (a, b) = (aging_parameters[0], aging_parameters[1]);
     a * (x * b).exp()
 }
@@ -15,6 +14,53 @@ pub fn aging_gompertz_makeham(x: f64, aging_parameters: &[f64]) -> f64 {
     c + a * (x * b).exp()
 }
 
+pub fn aging_siler(x: f64, aging_parameters: &[f64]) -> f64 {
+    let (a1, b1, a2, a3, b3) = (
+        aging_parameters[0],
+        aging_parameters[1],
+        aging_parameters[2],
+        aging_parameters[3],
+        aging_parameters[4],
+    );
+    a1 * (-b1 * x).exp() + a2 + a3 * (b3 * x).exp()
+}
+
+pub fn aging_weibull(x: f64, aging_parameters: &[f64]) -> f64 {
+    let (a, b) = (aging_parameters[0], aging_parameters[1]);
+    a * x.powf(b)
+}
+
+/// Perks (logistic) law: Gompertz-Makeham with a late-life deceleration set by s.
+pub fn aging_logistic(x: f64, aging_parameters: &[f64]) -> f64 {
+    let (a, b, c, s) = (
+        aging_parameters[0],
+        aging_parameters[1],
+        aging_parameters[2],
+        aging_parameters[3],
+    );
+    let gompertz = a * (b * x).exp();
+    c + gompertz / (1_f64 + s * a / b * ((b * x).exp() - 1_f64))
+}
+
+/// Heligman-Pollard law. The eight parameters give the odds of dying q/(1-q);
+/// the hazard returned is ln(1 + odds), the force of mortality giving that q.
+pub fn aging_heligman_pollard(x: f64, aging_parameters: &[f64]) -> f64 {
+    let (a, b, c, d, e, f, g, h) = (
+        aging_parameters[0],
+        aging_parameters[1],
+        aging_parameters[2],
+        aging_parameters[3],
+        aging_parameters[4],
+        aging_parameters[5],
+        aging_parameters[6],
+        aging_parameters[7],
+    );
+    let childhood = a.powf((x + b).powf(c));
+    let accident_hump = d * (-e * (x.ln() - f.ln()).powi(2)).exp();
+    let senescence = g * h.powf(x);
+    (childhood + accident_hump + senescence).ln_1p()
+}
+
 pub fn learning_function(x: f64, learning_parameters: &[f64]) -> f64 {
     let (lmax, k, n) = (
         learning_parameters[0],
@@ -42,63 +88,191 @@ pub fn fertility_brass_polynomial(x: f64, fertility_parameters: &[f64]) -> f64 {
     }
 }
 
-pub fn constant_fertility(_x: f64, fertility_parameters: &[f64]) -> f64 {
-    let c = fertility_parameters[0];
-    c
+/// Linear rise from 0 at age d to c at the end of the window of width w.
+pub fn fertility_increasing(x: f64, fertility_parameters: &[f64]) -> f64 {
+    let (c, d, w) = (
+        fertility_parameters[0],
+        fertility_parameters[1],
+        fertility_parameters[2],
+    );
+    if (x > d) && (x < (d + w)) {
+        c * (x - d) / w
+    } else {
+        0_f64
+    }
+}
+
+/// Linear decline from c at age d to 0 at the end of the window of width w.
+pub fn fertility_decreasing(x: f64, fertility_parameters: &[f64]) -> f64 {
+    let (c, d, w) = (
+        fertility_parameters[0],
+        fertility_parameters[1],
+        fertility_parameters[2],
+    );
+    if (x > d) && (x < (d + w)) {
+        c * (d + w - x) / w
+    } else {
+        0_f64
+    }
 }
 
+/// Hadwiger function with total fertility a, shape b and location c.
+pub fn fertility_hadwiger(x: f64, fertility_parameters: &[f64]) -> f64 {
+    let (a, b, c) = (
+        fertility_parameters[0],
+        fertility_parameters[1],
+        fertility_parameters[2],
+    );
+    if x <= 0_f64 {
+        return 0_f64;
+    }
+    a * b / c * (c / x).powf(1.5) * (-b.powi(2) * (c / x + x / c - 2_f64)).exp()
+}
 
+/// Gamma density of shape k and scale theta starting at age d, scaled by the total fertility r.
+pub fn fertility_gamma(x: f64, fertility_parameters: &[f64]) -> f64 {
+    let (r, d, k, theta) = (
+        fertility_parameters[0],
+        fertility_parameters[1],
+        fertility_parameters[2],
+        fertility_parameters[3],
+    );
+    if x <= d {
+        return 0_f64;
+    }
+    let log_density = (k - 1_f64) * (x - d).ln() - (x - d) / theta - ln_gamma(k) - k * theta.ln();
+    r * log_density.exp()
+}
 
-pub fn gla_model<T>(
-    x: f64,
-    aging_func: T,
-    learning_func: T,
-    growth_func: T,
-    aging_parameters: &[f64],
-    learning_parameters: &[f64],
-    growth_parameters: &[f64],
-    minimum_mortality: f64,
-) -> f64
-where
-    T: Fn(f64, &[f64]) -> f64,
-{
-    let aging_result = aging_func(x, aging_parameters);
-    let learning_result = learning_func(x, learning_parameters);
-    let growth_result = growth_func(x, growth_parameters);
+/// Coale-Trussell natural fertility schedule n(x) by five year age groups from 15 to 49.
+const COALE_TRUSSELL_NATURAL_FERTILITY: [f64; 7] = [0.411, 0.460, 0.431, 0.396, 0.321, 0.167, 0.024];
+/// Coale-Trussell typical deviation v(x) of controlled fertility, same age groups.
+const COALE_TRUSSELL_CONTROL_PATTERN: [f64; 7] = [0.0, 0.0, -0.279, -0.667, -1.042, -1.414, -1.671];
 
-    let gla_result = aging_result + learning_result + growth_result;
+/// Coale-Trussell model `M n(x) exp(m v(x))` with level M and control m.
+pub fn fertility_coale_trussell(x: f64, fertility_parameters: &[f64]) -> f64 {
+    let (level, control) = (fertility_parameters[0], fertility_parameters[1]);
+    if !(15_f64..50_f64).contains(&x) {
+        return 0_f64;
+    }
+    let age_group = ((x - 15_f64) / 5_f64) as usize;
+    level
+        * COALE_TRUSSELL_NATURAL_FERTILITY[age_group]
+        * (control * COALE_TRUSSELL_CONTROL_PATTERN[age_group]).exp()
+}
 
-    if gla_result < minimum_mortality {
-        minimum_mortality
-    } else {
-        gla_result
+pub fn constant_fertility(_x: f64, fertility_parameters: &[f64]) -> f64 {
+    fertility_parameters[0]
+}
+
+/// Oldest age searched for a maximum when the schedule never stops.
+pub const MAXIMUM_SEARCH_AGE: f64 = 150.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FertilityMaximum {
+    pub age: f64,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FertilityError {
+    /// The schedule is nowhere positive on its support, so it cannot be normalized.
+    NonPositiveMaximum { age: f64, value: f64 },
+    /// The schedule diverges at `age`.
+    UnboundedMaximum { age: f64 },
+    InvalidSupport(f64, f64),
+}
+
+impl std::fmt::Display for FertilityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FertilityError::NonPositiveMaximum { age, value } => write!(
+                f,
+                "the fertility schedule has no positive maximum (best value {} at age {})",
+                value, age
+            ),
+            FertilityError::UnboundedMaximum { age } => {
+                write!(f, "the fertility schedule diverges at age {}", age)
+            }
+            FertilityError::InvalidSupport(start, end) => {
+                write!(f, "invalid reproductive window [{}, {}]", start, end)
+            }
+        }
     }
 }
 
+impl std::error::Error for FertilityError {}
+
+/// Numeric maximum of a fertility schedule over its reproductive window.
+///
+/// A grid scan over `[start, end]` (capped at `MAXIMUM_SEARCH_AGE`) brackets
+/// the highest point, which golden-section search then refines, so flat zero
+/// regions and step functions do not trap the search the way a local
+/// optimizer started from a single guess can be trapped.
 pub fn find_maximum_fertility<T>(
     fertility_function: &T,
     fertility_parameters: &[f64],
-    first_guess: f64,
-) -> f64
+    support: (f64, f64),
+) -> Result<FertilityMaximum, FertilityError>
 where
     T: Fn(f64, &[f64]) -> f64,
 {
-    let fertility_cost_function =
-        |x: ArrayView1<f64>| -fertility_function(x[0], fertility_parameters);
+    let (start, end) = (support.0, support.1.min(MAXIMUM_SEARCH_AGE));
+    if !(start.is_finite() && end.is_finite() && start < end) {
+        return Err(FertilityError::InvalidSupport(support.0, support.1));
+    }
 
-    // Create a minimizer using the builder pattern. If some of the parameters are not given, default values are used.
-    let minimizer = NelderMeadBuilder::default()
-        .xtol(1e-6f64)
-        .ftol(1e-6f64)
-        .maxiter(50000)
-        .build()
-        .unwrap();
+    let fertility = |x: f64| {
+        let value = fertility_function(x, fertility_parameters);
+        if value.is_nan() {
+            f64::NEG_INFINITY
+        } else {
+            value
+        }
+    };
 
-    // Set the starting guess
-    let args = Array::from_vec(vec![first_guess]);
+    let grid_size = 2000;
+    let grid_step = (end - start) / grid_size as f64;
+    let (best_index, _) = (0..=grid_size)
+        .map(|i| fertility(start + i as f64 * grid_step))
+        .enumerate()
+        .fold((0, f64::NEG_INFINITY), |best, (i, value)| {
+            if value > best.1 {
+                (i, value)
+            } else {
+                best
+            }
+        });
 
-    // Run the optimization
-    let ans = minimizer.minimize(&fertility_cost_function, args.view());
+    let mut lower = start + best_index.saturating_sub(1) as f64 * grid_step;
+    let mut upper = (start + (best_index + 1) as f64 * grid_step).min(end);
+    let inverse_golden_ratio = (5_f64.sqrt() - 1_f64) / 2_f64;
+    let mut best = FertilityMaximum {
+        age: start + best_index as f64 * grid_step,
+        value: fertility(start + best_index as f64 * grid_step),
+    };
+    for _ in 0..100 {
+        let left = upper - inverse_golden_ratio * (upper - lower);
+        let right = lower + inverse_golden_ratio * (upper - lower);
+        let (left_value, right_value) = (fertility(left), fertility(right));
+        for (age, value) in [(left, left_value), (right, right_value)] {
+            if value > best.value {
+                best = FertilityMaximum { age, value };
+            }
+        }
+        if left_value >= right_value {
+            upper = right;
+        } else {
+            lower = left;
+        }
+    }
 
-    fertility_function(ans[0], fertility_parameters)
+    if best.value > 0_f64 && best.value.is_finite() {
+        Ok(best)
+    } else {
+        Err(FertilityError::NonPositiveMaximum {
+            age: best.age,
+            value: best.value,
+        })
+    }
 }
@@ -0,0 +1,477 @@
+use peroxide::fuga::GaussLegendre;
+use peroxide::numerical::integral::integrate;
+use serde::Deserialize;
+
+use crate::gla_package::gla::{
+    aging_gompertz, aging_gompertz_makeham, aging_heligman_pollard, aging_logistic, aging_siler,
+    aging_weibull, growth_function, learning_function,
+};
+
+/// One additive term of the hazard. Components are stateless: the parameters
+/// are carried by each agent so that they can evolve.
+pub trait HazardComponent: Send + Sync {
+    fn parameter_names(&self) -> &[&'static str];
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64;
+
+    /// Integral over `[from, to]` when it has a closed form.
+    fn integral(&self, _from: f64, _to: f64, _parameters: &[f64]) -> Option<f64> {
+        None
+    }
+
+    /// Lower bound of the component over `[from, to]`, when one is cheap.
+    fn lower_bound(&self, _from: f64, _to: f64, _parameters: &[f64]) -> Option<f64> {
+        None
+    }
+}
+
+/// Minimum over `[from, to]` of a monotone component.
+fn monotone_minimum(
+    component: &dyn HazardComponent,
+    from: f64,
+    to: f64,
+    parameters: &[f64],
+) -> Option<f64> {
+    Some(
+        component
+            .evaluate(from, parameters)
+            .min(component.evaluate(to, parameters)),
+    )
+}
+
+/// `c + a * exp(b * x)`
+pub struct GompertzMakeham;
+
+impl HazardComponent for GompertzMakeham {
+    fn parameter_names(&self) -> &[&'static str] {
+        &["a", "b", "c"]
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        aging_gompertz_makeham(x, parameters)
+    }
+
+    fn integral(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        let (a, b, c) = (parameters[0], parameters[1], parameters[2]);
+        Some(c * (to - from) + gompertz_integral(a, b, from, to))
+    }
+
+    fn lower_bound(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        monotone_minimum(self, from, to, parameters)
+    }
+}
+
+/// `a * exp(b * x)`
+pub struct Gompertz;
+
+impl HazardComponent for Gompertz {
+    fn parameter_names(&self) -> &[&'static str] {
+        &["a", "b"]
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        aging_gompertz(x, parameters)
+    }
+
+    fn integral(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        Some(gompertz_integral(parameters[0], parameters[1], from, to))
+    }
+
+    fn lower_bound(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        monotone_minimum(self, from, to, parameters)
+    }
+}
+
+fn gompertz_integral(a: f64, b: f64, from: f64, to: f64) -> f64 {
+    if b.abs() < 1e-12 {
+        a * (to - from)
+    } else {
+        a / b * ((b * to).exp() - (b * from).exp())
+    }
+}
+
+/// `a1 * exp(-b1 * x) + a2 + a3 * exp(b3 * x)`: infant, constant and senescent mortality.
+pub struct Siler;
+
+impl HazardComponent for Siler {
+    fn parameter_names(&self) -> &[&'static str] {
+        &["a1", "b1", "a2", "a3", "b3"]
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        aging_siler(x, parameters)
+    }
+
+    fn integral(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        let (a1, b1, a2, a3, b3) = (
+            parameters[0],
+            parameters[1],
+            parameters[2],
+            parameters[3],
+            parameters[4],
+        );
+        Some(
+            gompertz_integral(a1, -b1, from, to)
+                + a2 * (to - from)
+                + gompertz_integral(a3, b3, from, to),
+        )
+    }
+
+    fn lower_bound(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        // Each of the three terms is monotone, unlike their sum.
+        let (a1, b1, a2, a3, b3) = (
+            parameters[0],
+            parameters[1],
+            parameters[2],
+            parameters[3],
+            parameters[4],
+        );
+        let infant = monotone_minimum(&Gompertz, from, to, &[a1, -b1])?;
+        let senescent = monotone_minimum(&Gompertz, from, to, &[a3, b3])?;
+        Some(infant + a2 + senescent)
+    }
+}
+
+/// `a * x^b`
+pub struct Weibull;
+
+impl HazardComponent for Weibull {
+    fn parameter_names(&self) -> &[&'static str] {
+        &["a", "b"]
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        aging_weibull(x, parameters)
+    }
+
+    fn integral(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        let (a, b) = (parameters[0], parameters[1]);
+        if b <= -1.0 {
+            return None;
+        }
+        Some(a / (b + 1.0) * (to.powf(b + 1.0) - from.powf(b + 1.0)))
+    }
+
+    fn lower_bound(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        monotone_minimum(self, from, to, parameters)
+    }
+}
+
+/// Perks law `c + a * exp(b * x) / (1 + s * a / b * (exp(b * x) - 1))`,
+/// reducing to Gompertz-Makeham when `s` is 0.
+pub struct Logistic;
+
+impl HazardComponent for Logistic {
+    fn parameter_names(&self) -> &[&'static str] {
+        &["a", "b", "c", "s"]
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        aging_logistic(x, parameters)
+    }
+
+    fn integral(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        let (a, b, c, s) = (parameters[0], parameters[1], parameters[2], parameters[3]);
+        if b.abs() < 1e-12 {
+            return None;
+        }
+        if s.abs() < 1e-12 {
+            return Some(c * (to - from) + gompertz_integral(a, b, from, to));
+        }
+        let primitive = |x: f64| (1.0 + s * a / b * ((b * x).exp() - 1.0)).ln() / s;
+        Some(c * (to - from) + primitive(to) - primitive(from))
+    }
+
+    fn lower_bound(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        monotone_minimum(self, from, to, parameters)
+    }
+}
+
+/// Heligman-Pollard law `ln(1 + A^((x+B)^C) + D exp(-E (ln x - ln F)^2) + G H^x)`.
+pub struct HeligmanPollard;
+
+impl HazardComponent for HeligmanPollard {
+    fn parameter_names(&self) -> &[&'static str] {
+        &["A", "B", "C", "D", "E", "F", "G", "H"]
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        aging_heligman_pollard(x, parameters)
+    }
+}
+
+/// Age independent hazard `c`, e.g. extrinsic mortality.
+pub struct ConstantHazard;
+
+impl HazardComponent for ConstantHazard {
+    fn parameter_names(&self) -> &[&'static str] {
+        &["c"]
+    }
+
+    fn evaluate(&self, _x: f64, parameters: &[f64]) -> f64 {
+        parameters[0]
+    }
+
+    fn integral(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        Some(parameters[0] * (to - from))
+    }
+
+    fn lower_bound(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        monotone_minimum(self, from, to, parameters)
+    }
+}
+
+/// Sigmoid decrease of the hazard with experience, see `learning_function`.
+pub struct Learning;
+
+impl HazardComponent for Learning {
+    fn parameter_names(&self) -> &[&'static str] {
+        &["lmax", "k", "n"]
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        learning_function(x, parameters)
+    }
+
+    fn integral(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        let (lmax, k, n) = (parameters[0], parameters[1], parameters[2]);
+        if n.abs() < 1e-12 {
+            return Some(-lmax / 2.0 * (to - from));
+        }
+        // The integrand is -lmax * sigmoid(n * (x - k)), whose primitive is a softplus.
+        let softplus = |u: f64| u.max(0.0) + (-u.abs()).exp().ln_1p();
+        Some(-lmax / n * (softplus(n * (to - k)) - softplus(n * (from - k))))
+    }
+
+    fn lower_bound(&self, from: f64, to: f64, parameters: &[f64]) -> Option<f64> {
+        monotone_minimum(self, from, to, parameters)
+    }
+}
+
+/// Decrease of the hazard with body growth, see `growth_function`.
+pub struct Growth;
+
+impl HazardComponent for Growth {
+    fn parameter_names(&self) -> &[&'static str] {
+        &["gmax", "growth_rate"]
+    }
+
+    fn evaluate(&self, x: f64, parameters: &[f64]) -> f64 {
+        growth_function(x, parameters)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HazardKind {
+    Constant,
+    Gompertz,
+    GompertzMakeham,
+    Siler,
+    Weibull,
+    #[serde(alias = "perks")]
+    Logistic,
+    HeligmanPollard,
+    Learning,
+    Growth,
+}
+
+impl HazardKind {
+    pub fn component(&self) -> Box<dyn HazardComponent> {
+        match self {
+            HazardKind::Constant => Box::new(ConstantHazard),
+            HazardKind::Gompertz => Box::new(Gompertz),
+            HazardKind::GompertzMakeham => Box::new(GompertzMakeham),
+            HazardKind::Siler => Box::new(Siler),
+            HazardKind::Weibull => Box::new(Weibull),
+            HazardKind::Logistic => Box::new(Logistic),
+            HazardKind::HeligmanPollard => Box::new(HeligmanPollard),
+            HazardKind::Learning => Box::new(Learning),
+            HazardKind::Growth => Box::new(Growth),
+        }
+    }
+}
+
+struct HazardTerm {
+    name: String,
+    component: Box<dyn HazardComponent>,
+    baseline_parameters: Vec<f64>,
+}
+
+/// Sum of any number of named hazard components, floored at `minimum_mortality`.
+///
+/// Agents carry one parameter vector per component, in the order the
+/// components were added; `baseline_parameters` gives the vectors founders
+/// start from.
+pub struct HazardModel {
+    terms: Vec<HazardTerm>,
+    minimum_mortality: f64,
+}
+
+impl HazardModel {
+    pub fn new(minimum_mortality: f64) -> Self {
+        HazardModel {
+            terms: Vec::new(),
+            minimum_mortality,
+        }
+    }
+
+    pub fn with_component(
+        mut self,
+        name: &str,
+        component: Box<dyn HazardComponent>,
+        baseline_parameters: Vec<f64>,
+    ) -> Self {
+        self.terms.push(HazardTerm {
+            name: name.to_string(),
+            component,
+            baseline_parameters,
+        });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn component_index(&self, name: &str) -> Option<usize> {
+        self.terms.iter().position(|term| term.name == name)
+    }
+
+    pub fn component(&self, index: usize) -> &dyn HazardComponent {
+        &*self.terms[index].component
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.terms.iter().map(|term| term.name.as_str()).collect()
+    }
+
+    pub fn baseline_parameters(&self) -> Vec<Vec<f64>> {
+        self.terms
+            .iter()
+            .map(|term| term.baseline_parameters.clone())
+            .collect()
+    }
+
+    pub fn evaluate(&self, x: f64, parameters: &[Vec<f64>]) -> f64 {
+        let hazard = self
+            .terms
+            .iter()
+            .zip(parameters)
+            .map(|(term, term_parameters)| term.component.evaluate(x, term_parameters))
+            .sum::<f64>();
+        hazard.max(self.minimum_mortality)
+    }
+
+    /// Integral of the hazard over `[from, to]`.
+    ///
+    /// Uses the closed forms when every component has one and the sum of
+    /// their lower bounds stays above the floor, so that the floor cannot be
+    /// reached anywhere in the interval, Gauss-Legendre quadrature otherwise.
+    pub fn cumulative_hazard(&self, from: f64, to: f64, parameters: &[Vec<f64>]) -> f64 {
+        if let Some(integral) = self.analytic_cumulative_hazard(from, to, parameters) {
+            return integral;
+        }
+        integrate(
+            |x: f64| -> f64 { self.evaluate(x, parameters) },
+            (from, to),
+            GaussLegendre(5),
+        )
+    }
+
+    fn analytic_cumulative_hazard(&self, from: f64, to: f64, parameters: &[Vec<f64>]) -> Option<f64> {
+        let integral = self
+            .terms
+            .iter()
+            .zip(parameters)
+            .map(|(term, term_parameters)| term.component.integral(from, to, term_parameters))
+            .sum::<Option<f64>>()?;
+
+        let lower_bound = self
+            .terms
+            .iter()
+            .zip(parameters)
+            .map(|(term, term_parameters)| term.component.lower_bound(from, to, term_parameters))
+            .sum::<Option<f64>>()?;
+        (lower_bound >= self.minimum_mortality).then_some(integral)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Composite Simpson rule, fine enough to serve as the reference.
+    fn simpson(f: impl Fn(f64) -> f64, from: f64, to: f64) -> f64 {
+        let n = 20_000;
+        let h = (to - from) / n as f64;
+        let inner: f64 = (1..n)
+            .map(|i| f(from + i as f64 * h) * if i % 2 == 1 { 4.0 } else { 2.0 })
+            .sum();
+        (f(from) + inner + f(to)) * h / 3.0
+    }
+
+    #[test]
+    fn closed_form_integrals_match_quadrature() {
+        let cases: [(&dyn HazardComponent, Vec<f64>, _); 19] = [
+            (&GompertzMakeham, vec![1e-4, 0.1, 1e-3], (0.0, 1.0)),
+            (&GompertzMakeham, vec![1e-4, 0.1, 1e-3], (80.0, 81.0)),
+            (&Gompertz, vec![1e-4, 0.1], (0.0, 100.0)),
+            (&Gompertz, vec![0.01, 1e-13], (5.0, 6.0)),
+            (&Siler, vec![0.05, 1.5, 2e-3, 1e-4, 0.1], (0.0, 1.0)),
+            (&Siler, vec![0.05, 1.5, 2e-3, 1e-4, 0.1], (0.0, 50.0)),
+            (&Siler, vec![0.05, 1.5, 2e-3, 1e-4, 0.1], (70.0, 71.0)),
+            (&Weibull, vec![1e-6, 3.0], (0.5, 1.5)),
+            (&Weibull, vec![1e-6, 3.0], (60.0, 61.0)),
+            (&Weibull, vec![0.01, -0.5], (0.5, 20.0)),
+            (&Weibull, vec![0.01, -0.999], (1.0, 2.0)),
+            // The logistic hazard is half way to its plateau near age 62.
+            (&Logistic, vec![1e-4, 0.1, 1e-3, 2.0], (61.5, 62.5)),
+            (&Logistic, vec![1e-4, 0.1, 1e-3, 2.0], (0.0, 100.0)),
+            (&Logistic, vec![1e-4, 0.1, 1e-3, 0.0], (80.0, 81.0)),
+            (&ConstantHazard, vec![0.01], (3.0, 4.0)),
+            (&Learning, vec![0.1, 20.0, 0.5], (19.5, 20.5)),
+            (&Learning, vec![0.1, 20.0, 0.5], (0.0, 100.0)),
+            (&Learning, vec![0.1, 20.0, -0.5], (0.0, 40.0)),
+            (&Learning, vec![0.1, 20.0, 0.0], (0.0, 1.0)),
+        ];
+        for (component, parameters, (from, to)) in cases {
+            let closed_form = component.integral(from, to, &parameters).unwrap();
+            let numeric = simpson(|x| component.evaluate(x, &parameters), from, to);
+            assert!(
+                (closed_form - numeric).abs() <= 1e-9 * numeric.abs().max(1e-6),
+                "{parameters:?} over [{from}, {to}]: {closed_form} vs {numeric}"
+            );
+        }
+    }
+
+    #[test]
+    fn floor_reached_between_samples_falls_back_to_quadrature() {
+        // A bathtub with its minimum of 0.04 at age 1.8, above the floor at
+        // both ends and in the middle of [1.5, 2.9] but below it around 1.8.
+        let siler = vec![0.02 * 5.4f64.exp(), 3.0, 0.0, 0.02 * (-5.4f64).exp(), 3.0];
+        let learning = vec![1e-3, 20.0, 0.5];
+        let model = HazardModel::new(0.045)
+            .with_component("siler", Box::new(Siler), siler.clone())
+            .with_component("learning", Box::new(Learning), learning.clone());
+        let parameters = vec![siler, learning];
+        let (from, to) = (1.5, 2.9);
+        for x in [from, (from + to) / 2.0, to] {
+            assert!(
+                Siler.evaluate(x, &parameters[0]) + Learning.evaluate(x, &parameters[1]) > 0.045
+            );
+        }
+        let numeric = simpson(|x| model.evaluate(x, &parameters), from, to);
+        let closed_form = Siler.integral(from, to, &parameters[0]).unwrap()
+            + Learning.integral(from, to, &parameters[1]).unwrap();
+        let cumulative = model.cumulative_hazard(from, to, &parameters);
+        assert!(
+            (cumulative - numeric).abs() < (closed_form - numeric).abs(),
+            "{numeric} {closed_form} {cumulative}"
+        );
+    }
+}
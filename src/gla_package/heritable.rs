@@ -0,0 +1,339 @@
+use rand::Rng;
+use rand_distr::{Distribution, Exp1, Normal, Uniform};
+use serde::Deserialize;
+
+/// Position of a heritable value inside an agent's parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraitLocation {
+    /// Parameter `index` of the hazard component at position `component`.
+    Hazard { component: usize, index: usize },
+    /// Parameter `index` of the female or male fertility schedule.
+    Fertility { female: bool, index: usize },
+    /// Neutral parameter `index`, acting only through tradeoffs and mate choice.
+    Neutral { index: usize },
+    /// Proportion of sons among the offspring of a female.
+    SexRatio,
+}
+
+/// Where an offspring's value of a parameter comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Inheritance {
+    /// The model's value, whatever the parents carry.
+    Baseline,
+    Maternal,
+    Paternal,
+    /// Mean of the parents' values.
+    Midparent,
+}
+
+impl Inheritance {
+    pub fn value(&self, baseline: f64, father: f64, mother: f64) -> f64 {
+        match self {
+            Inheritance::Baseline => baseline,
+            Inheritance::Maternal => mother,
+            Inheritance::Paternal => father,
+            Inheritance::Midparent => (father + mother) / 2.0,
+        }
+    }
+}
+
+/// Baseline value and inheritance of one parameter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InheritedParameter {
+    pub baseline: f64,
+    pub inheritance: Inheritance,
+}
+
+impl InheritedParameter {
+    pub fn value(&self, father: f64, mother: f64) -> f64 {
+        self.inheritance.value(self.baseline, father, mother)
+    }
+}
+
+/// How a newborn gets each of its parameters, laid out like the parameters
+/// of an agent. Heritable traits then replace the values at their locations.
+#[derive(Clone, Debug)]
+pub struct OffspringTemplate {
+    pub hazard: Vec<Vec<InheritedParameter>>,
+    pub female_fertility: Vec<InheritedParameter>,
+    pub male_fertility: Vec<InheritedParameter>,
+    pub neutral: Vec<InheritedParameter>,
+    pub sex_ratio: InheritedParameter,
+}
+
+impl OffspringTemplate {
+    /// Every parameter at its baseline value.
+    pub fn baseline(
+        hazard: &[Vec<f64>],
+        female_fertility: &[f64],
+        male_fertility: &[f64],
+        neutral: &[f64],
+        sex_ratio: f64,
+    ) -> Self {
+        let parameter = |baseline| InheritedParameter {
+            baseline,
+            inheritance: Inheritance::Baseline,
+        };
+        let baseline = |values: &[f64]| values.iter().map(|&baseline| parameter(baseline)).collect::<Vec<_>>();
+        OffspringTemplate {
+            hazard: hazard.iter().map(|component| baseline(component)).collect(),
+            female_fertility: baseline(female_fertility),
+            male_fertility: baseline(male_fertility),
+            neutral: baseline(neutral),
+            sex_ratio: parameter(sex_ratio),
+        }
+    }
+
+    pub fn with_inheritance(mut self, location: TraitLocation, inheritance: Inheritance) -> Self {
+        self.parameter_mut(location).inheritance = inheritance;
+        self
+    }
+
+    pub fn parameter(&self, location: TraitLocation) -> &InheritedParameter {
+        match location {
+            TraitLocation::Hazard { component, index } => &self.hazard[component][index],
+            TraitLocation::Fertility { female: true, index } => &self.female_fertility[index],
+            TraitLocation::Fertility { female: false, index } => &self.male_fertility[index],
+            TraitLocation::Neutral { index } => &self.neutral[index],
+            TraitLocation::SexRatio => &self.sex_ratio,
+        }
+    }
+
+    fn parameter_mut(&mut self, location: TraitLocation) -> &mut InheritedParameter {
+        match location {
+            TraitLocation::Hazard { component, index } => &mut self.hazard[component][index],
+            TraitLocation::Fertility { female: true, index } => &mut self.female_fertility[index],
+            TraitLocation::Fertility { female: false, index } => &mut self.male_fertility[index],
+            TraitLocation::Neutral { index } => &mut self.neutral[index],
+            TraitLocation::SexRatio => &mut self.sex_ratio,
+        }
+    }
+}
+
+/// Distribution a mutated value is drawn from, centred on the parental value
+/// shifted by the bias.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MutationKernel {
+    /// Normal with the mutation strength as standard deviation.
+    #[default]
+    Gaussian,
+    /// Laplace with the strength as scale, heavier tailed than the Gaussian.
+    Laplace,
+    /// Multiplies the value by `exp(N(bias, strength))`, so the sign never changes.
+    LogNormal,
+    /// Uniform within the strength of the value.
+    Uniform,
+    /// Plus or minus the strength with equal probability.
+    DiscreteStep,
+}
+
+impl MutationKernel {
+    pub fn sample(&self, value: f64, strength: f64, bias: f64, rng: &mut impl Rng) -> f64 {
+        let centre = value + bias;
+        match self {
+            MutationKernel::Gaussian => Normal::new(centre, strength).unwrap().sample(rng),
+            MutationKernel::Laplace => {
+                // An exponential distance in a random direction.
+                let distance: f64 = strength * Distribution::<f64>::sample(&Exp1, rng);
+                if rng.gen::<f64>() < 0.5 {
+                    centre - distance
+                } else {
+                    centre + distance
+                }
+            }
+            MutationKernel::LogNormal => value * Normal::new(bias, strength).unwrap().sample(rng).exp(),
+            MutationKernel::Uniform => {
+                if strength > 0.0 {
+                    centre + Uniform::new_inclusive(-strength, strength).sample(rng)
+                } else {
+                    centre
+                }
+            }
+            MutationKernel::DiscreteStep => {
+                if rng.gen::<f64>() < 0.5 {
+                    centre - strength
+                } else {
+                    centre + strength
+                }
+            }
+        }
+    }
+}
+
+/// What happens to a value leaving the bounds of its trait.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Boundary {
+    /// Set to the bound it crossed, which piles values up at the bound.
+    #[default]
+    Clamp,
+    /// Mirrored back inside by the distance it overshot.
+    Reflecting,
+    /// Set to the bound it crossed and kept there: values at a bound no longer
+    /// mutate. Alleles of traits with loci still do.
+    Absorbing,
+}
+
+/// Diploid architecture of a trait: `loci` loci with additive allelic
+/// effects, the phenotype being the sum of the 2 * `loci` alleles plus
+/// environmental noise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Genetics {
+    pub loci: usize,
+    /// Probability of a crossover between two adjacent loci, 0.5 for free
+    /// recombination, less for loci linked on one chromosome.
+    pub recombination_rate: f64,
+    /// Standard deviation of the non-heritable part of the phenotype.
+    pub environmental_noise: f64,
+}
+
+/// Alleles of one trait, one [maternal, paternal] pair per locus.
+pub type Genotype = Vec<[f64; 2]>;
+
+/// A parameter that varies between agents: founders draw it from
+/// `initial_distribution` and offspring inherit it with mutation, either
+/// according to `inheritance` or through Mendelian loci when `genetics` is set.
+#[derive(Clone, Debug)]
+pub struct HeritableTrait {
+    /// Used in the output columns, e.g. `mean_b` for "b".
+    pub name: String,
+    pub location: TraitLocation,
+    /// [mean, standard deviation] of the founders.
+    pub initial_distribution: [f64; 2],
+    /// Maternal, paternal or midparent, ignored with loci.
+    pub inheritance: Inheritance,
+    /// Probability that an offspring's value mutates, 0 to switch evolution off.
+    pub mutation_rate: f64,
+    pub mutation_strength: f64,
+    pub kernel: MutationKernel,
+    /// Mean shift of the mutations, on the log scale for the log-normal kernel.
+    pub mutation_bias: f64,
+    /// [lower, upper] limits of the value.
+    pub bounds: [f64; 2],
+    pub boundary: Boundary,
+    /// With loci, `mutation_rate` applies to each allele passed on.
+    pub genetics: Option<Genetics>,
+}
+
+impl HeritableTrait {
+    pub fn founder_value(&self, rng: &mut impl Rng) -> f64 {
+        let [mean, standard_deviation] = self.initial_distribution;
+        let value = Normal::new(mean, standard_deviation).unwrap().sample(rng);
+        self.bound(value)
+    }
+
+    /// Mutates `value` with probability `mutation_rate`.
+    pub fn mutate(&self, value: f64, rng: &mut impl Rng) -> f64 {
+        if self.boundary == Boundary::Absorbing && self.bounds.contains(&value) {
+            return value;
+        }
+        if self.mutation_rate > 0.0 && rng.gen::<f64>() < self.mutation_rate {
+            self.bound(self.kernel.sample(value, self.mutation_strength, self.mutation_bias, rng))
+        } else {
+            value
+        }
+    }
+
+    /// Founder alleles, drawn so that their sum follows `initial_distribution`.
+    pub fn founder_genotype(&self, genetics: &Genetics, rng: &mut impl Rng) -> Genotype {
+        let [mean, standard_deviation] = self.initial_distribution;
+        let alleles = 2.0 * genetics.loci as f64;
+        let allele_distribution = Normal::new(mean / alleles, standard_deviation / alleles.sqrt()).unwrap();
+        (0..genetics.loci)
+            .map(|_| [allele_distribution.sample(rng), allele_distribution.sample(rng)])
+            .collect()
+    }
+
+    /// Sum of the allelic effects plus environmental noise, within the bounds.
+    pub fn phenotype(&self, genetics: &Genetics, genotype: &Genotype, rng: &mut impl Rng) -> f64 {
+        let genetic_value = genotype.iter().map(|locus| locus[0] + locus[1]).sum::<f64>();
+        let noise = if genetics.environmental_noise > 0.0 {
+            Normal::new(0.0, genetics.environmental_noise).unwrap().sample(rng)
+        } else {
+            0.0
+        };
+        self.bound(genetic_value + noise)
+    }
+
+    /// Haploid set of alleles passed on by a parent: one copy per locus,
+    /// switching copy after a crossover, each allele mutating independently.
+    pub fn gamete(&self, genetics: &Genetics, genotype: &Genotype, rng: &mut impl Rng) -> Vec<f64> {
+        let mut copy = rng.gen_range(0..2);
+        genotype
+            .iter()
+            .enumerate()
+            .map(|(locus, alleles)| {
+                if locus > 0 && rng.gen::<f64>() < genetics.recombination_rate {
+                    copy = 1 - copy;
+                }
+                let allele = alleles[copy];
+                if self.mutation_rate > 0.0 && rng.gen::<f64>() < self.mutation_rate {
+                    self.kernel.sample(allele, self.mutation_strength, self.mutation_bias, rng)
+                } else {
+                    allele
+                }
+            })
+            .collect()
+    }
+
+    /// Brings a value back within the bounds according to the boundary.
+    pub fn bound(&self, value: f64) -> f64 {
+        let [lower, upper] = self.bounds;
+        match self.boundary {
+            Boundary::Clamp | Boundary::Absorbing => value.max(lower).min(upper),
+            Boundary::Reflecting => {
+                if (lower..=upper).contains(&value) || lower == upper {
+                    return value.max(lower).min(upper);
+                }
+                let width = upper - lower;
+                if width.is_infinite() {
+                    return if value < lower { 2.0 * lower - value } else { 2.0 * upper - value };
+                }
+                // Reflections repeat with period 2 width: fold into one period, then mirror.
+                let offset = (value - lower).rem_euclid(2.0 * width);
+                let offset = if offset > width { 2.0 * width - offset } else { offset };
+                (lower + offset).max(lower).min(upper)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reflecting_trait(bounds: [f64; 2]) -> HeritableTrait {
+        HeritableTrait {
+            name: "b".to_string(),
+            location: TraitLocation::Hazard { component: 0, index: 1 },
+            initial_distribution: [0.5, 0.1],
+            inheritance: Inheritance::Midparent,
+            mutation_rate: 0.0,
+            mutation_strength: 0.0,
+            kernel: MutationKernel::default(),
+            mutation_bias: 0.0,
+            bounds,
+            boundary: Boundary::Reflecting,
+            genetics: None,
+        }
+    }
+
+    #[test]
+    fn reflection_folds_any_overshoot_into_the_bounds() {
+        let bounded = reflecting_trait([0.0, 1.0]);
+        assert_eq!(bounded.bound(0.25), 0.25);
+        assert_eq!(bounded.bound(-0.25), 0.25);
+        assert_eq!(bounded.bound(1.25), 0.75);
+        assert!((bounded.bound(2.25) - 0.25).abs() < 1e-12);
+        assert!((bounded.bound(-3.75) - 0.25).abs() < 1e-12);
+        for value in [1e17, -1e17, 1e300, f64::MAX] {
+            assert!((0.0..=1.0).contains(&bounded.bound(value)), "{}", value);
+        }
+
+        let half_open = reflecting_trait([0.0, f64::INFINITY]);
+        assert_eq!(half_open.bound(-2.0), 2.0);
+        assert_eq!(half_open.bound(1e300), 1e300);
+    }
+}
@@ -0,0 +1,84 @@
+use rand::Rng;
+use rand_distr::{Distribution, Poisson};
+use serde::Deserialize;
+
+/// Distribution of the number of offspring of a successful couple.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LitterSize {
+    /// Always `size` offspring.
+    #[default]
+    Fixed,
+    /// Poisson with mean `size`, so that a litter can be empty.
+    Poisson,
+    /// Poisson with a mean that depends on the mother's age class.
+    AgeDependent,
+}
+
+/// Litters and the sex of the offspring in them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LitterParameters {
+    #[serde(default)]
+    pub distribution: LitterSize,
+    /// Fixed size or Poisson mean of a litter.
+    #[serde(default = "default_size")]
+    pub size: f64,
+    /// Ages of the mother at which a new class starts, in increasing order,
+    /// age-dependent litters only.
+    #[serde(default)]
+    pub age_classes: Vec<f64>,
+    /// Poisson mean of the litters of each age class.
+    #[serde(default)]
+    pub sizes: Vec<f64>,
+    /// Baseline proportion of sons. Agents carry their own value, which can be
+    /// a heritable trait, and the mother's sets the sex of her offspring.
+    #[serde(default = "default_sex_ratio")]
+    pub sex_ratio: f64,
+}
+
+fn default_size() -> f64 {
+    1.0
+}
+
+fn default_sex_ratio() -> f64 {
+    0.5
+}
+
+impl Default for LitterParameters {
+    fn default() -> Self {
+        LitterParameters {
+            distribution: LitterSize::default(),
+            size: default_size(),
+            age_classes: Vec::new(),
+            sizes: Vec::new(),
+            sex_ratio: default_sex_ratio(),
+        }
+    }
+}
+
+impl LitterParameters {
+    pub fn number_of_classes(&self) -> usize {
+        self.age_classes.len() + 1
+    }
+
+    /// Mean size of the litters of a mother of age `age`.
+    pub fn mean_size(&self, age: f64) -> f64 {
+        match self.distribution {
+            LitterSize::Fixed | LitterSize::Poisson => self.size,
+            LitterSize::AgeDependent => {
+                self.sizes[self.age_classes.iter().take_while(|&&start| age >= start).count()]
+            }
+        }
+    }
+
+    /// Number of offspring of a mother of age `age`.
+    pub fn litter_size(&self, age: f64, rng: &mut impl Rng) -> usize {
+        let mean = self.mean_size(age);
+        match self.distribution {
+            LitterSize::Fixed => mean as usize,
+            _ if mean > 0.0 => Poisson::new(mean).unwrap().sample(rng) as usize,
+            _ => 0,
+        }
+    }
+}
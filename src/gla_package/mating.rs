@@ -0,0 +1,349 @@
+use std::collections::HashMap;
+
+use rand::distributions::WeightedIndex;
+use rand::seq::index;
+use rand::Rng;
+use rand_distr::{Distribution, Poisson};
+use serde::Deserialize;
+
+use crate::gla_package::agent_based::Agent;
+use crate::gla_package::heritable::{HeritableTrait, TraitLocation};
+
+/// How males and females meet to reproduce during a step.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatingSystem {
+    /// New male-female pairs every step, each agent in at most one pair.
+    #[default]
+    RandomPairs,
+    /// Pairs that persist from step to step until one partner dies; single
+    /// agents pair up among themselves.
+    Monogamy,
+    /// Every female mates with a random male, so that a male can father
+    /// several offspring per step.
+    Polygyny,
+    /// Mass action: a Poisson number of encounters with mean
+    /// `encounter_rate * females * males / population`, each between a random
+    /// female and a random male.
+    Promiscuous,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatingParameters {
+    #[serde(default)]
+    pub system: MatingSystem,
+    /// Encounters per female and male, promiscuous mating only.
+    #[serde(default = "default_encounter_rate")]
+    pub encounter_rate: f64,
+    /// Female mate choice, any male of the pair or encounter when absent.
+    #[serde(default)]
+    pub choice: Option<MateChoiceParameters>,
+}
+
+fn default_encounter_rate() -> f64 {
+    1.0
+}
+
+impl Default for MatingParameters {
+    fn default() -> Self {
+        MatingParameters {
+            system: MatingSystem::default(),
+            encounter_rate: default_encounter_rate(),
+            choice: None,
+        }
+    }
+}
+
+/// Mate choice as read from the configuration, see [`MateChoice`].
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MateChoiceParameters {
+    #[serde(default = "default_candidates")]
+    pub candidates: usize,
+    #[serde(default)]
+    pub age_difference: Option<[f64; 2]>,
+    #[serde(default)]
+    pub assortment_trait: Option<String>,
+    #[serde(default)]
+    pub assortment_strength: f64,
+    #[serde(default)]
+    pub display_trait: Option<String>,
+    #[serde(default)]
+    pub display_strength: f64,
+}
+
+fn default_candidates() -> usize {
+    10
+}
+
+/// Preference for the value of a heritable trait.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraitPreference {
+    pub location: TraitLocation,
+    pub strength: f64,
+}
+
+/// Female mate choice: a female meets `candidates` males at random and
+/// mates with one of them with probability proportional to her preference
+/// for him, the product of
+/// - `exp(-z^2 / 2)` with `z` the male's age minus hers, standardized by the
+///   [mean, standard deviation] of `age_difference`;
+/// - `exp(-strength d^2)` with `d` the difference between their values of the
+///   assortment trait, a negative strength favouring dissimilar mates;
+/// - `exp(strength v)` with `v` the male's value of the display trait.
+#[derive(Clone, Debug)]
+pub struct MateChoice {
+    pub candidates: usize,
+    pub age_difference: Option<[f64; 2]>,
+    pub assortment: Option<TraitPreference>,
+    pub display: Option<TraitPreference>,
+}
+
+impl MateChoice {
+    /// Logarithm of the preference of `female` for `male`, which stays finite
+    /// where the preference itself overflows or underflows.
+    pub fn log_preference(&self, male: &Agent, female: &Agent) -> f64 {
+        let mut log_preference = 0.0;
+        if let Some([mean, standard_deviation]) = self.age_difference {
+            let z = (male.age - female.age - mean) / standard_deviation;
+            log_preference -= 0.5 * z * z;
+        }
+        if let Some(assortment) = &self.assortment {
+            let difference = male.trait_value(assortment.location) - female.trait_value(assortment.location);
+            log_preference -= assortment.strength * difference * difference;
+        }
+        if let Some(display) = &self.display {
+            log_preference += display.strength * male.trait_value(display.location);
+        }
+        log_preference
+    }
+
+    /// Position in `males` of the male `female` chooses, none only when a
+    /// preference is undefined.
+    pub fn choose(&self, female: &Agent, males: &[usize], population: &[Agent], rng: &mut impl Rng) -> Option<usize> {
+        let candidates = index::sample(rng, males.len(), self.candidates.min(males.len())).into_vec();
+        let log_preferences: Vec<f64> = candidates
+            .iter()
+            .map(|&candidate| self.log_preference(&population[males[candidate]], female))
+            .collect();
+        // Relative to the preferred candidate, so that the weights lie in [0, 1]
+        // and the preferred one has weight 1.
+        let best = log_preferences.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let weights = log_preferences
+            .iter()
+            .map(|&log_preference| if log_preference == best { 1.0 } else { (log_preference - best).exp() });
+        let choice = WeightedIndex::new(weights).ok()?;
+        Some(candidates[choice.sample(rng)])
+    }
+}
+
+/// Correlations between the values of mates over the couples of a step, none
+/// when undefined.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct MatingCorrelations {
+    pub age: Option<f64>,
+    /// One per heritable trait, in declaration order.
+    pub traits: Vec<Option<f64>>,
+}
+
+impl MatingCorrelations {
+    pub fn new(couples: &[(&Agent, &Agent)], traits: &[HeritableTrait]) -> Self {
+        let correlation = |value: &dyn Fn(&Agent) -> f64| {
+            let pairs: Vec<(f64, f64)> = couples.iter().map(|(male, female)| (value(male), value(female))).collect();
+            pearson_correlation(&pairs)
+        };
+        MatingCorrelations {
+            age: correlation(&|agent| agent.age),
+            traits: traits
+                .iter()
+                .map(|heritable_trait| correlation(&|agent| agent.trait_value(heritable_trait.location)))
+                .collect(),
+        }
+    }
+}
+
+/// Pearson correlation, none without pairs or when a side is constant.
+fn pearson_correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+    let &(x0, y0) = pairs.first()?;
+    // Shifting by the first pair makes a constant side exactly constant at 0.
+    let pairs: Vec<(f64, f64)> = pairs.iter().map(|(x, y)| (x - x0, y - y0)).collect();
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|pair| pair.0).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|pair| pair.1).sum::<f64>() / n;
+    let (mut covariance, mut variance_x, mut variance_y) = (0.0, 0.0, 0.0);
+    for (x, y) in &pairs {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x).powi(2);
+        variance_y += (y - mean_y).powi(2);
+    }
+    let correlation = covariance / (variance_x * variance_y).sqrt();
+    correlation.is_finite().then_some(correlation)
+}
+
+/// Indices of the (male, female) couples of the step. Without mate choice,
+/// pairs form in population order, so sorting the population by age
+/// beforehand makes them assortative.
+pub fn form_couples(
+    population: &mut [Agent],
+    parameters: &MatingParameters,
+    choice: Option<&MateChoice>,
+    rng: &mut impl Rng,
+) -> Vec<(usize, usize)> {
+    let females: Vec<usize> = (0..population.len()).filter(|&index| population[index].female).collect();
+    let males: Vec<usize> = (0..population.len()).filter(|&index| !population[index].female).collect();
+    if females.is_empty() || males.is_empty() {
+        return Vec::new();
+    }
+
+    match parameters.system {
+        MatingSystem::RandomPairs => pair_up(population, &males, &females, choice, rng),
+        MatingSystem::Monogamy => monogamous_couples(population, &males, &females, choice, rng),
+        MatingSystem::Polygyny => females
+            .into_iter()
+            .filter_map(|female| choose_mate(population, female, &males, choice, rng).map(|male| (male, female)))
+            .collect(),
+        MatingSystem::Promiscuous => {
+            let mean = parameters.encounter_rate * (females.len() * males.len()) as f64 / population.len() as f64;
+            let encounters = if mean > 0.0 {
+                Poisson::new(mean).unwrap().sample(rng) as usize
+            } else {
+                0
+            };
+            (0..encounters)
+                .filter_map(|_| {
+                    let female = females[rng.gen_range(0..females.len())];
+                    choose_mate(population, female, &males, choice, rng).map(|male| (male, female))
+                })
+                .collect()
+        }
+    }
+}
+
+/// A random male for `female`, or the one she chooses.
+fn choose_mate(
+    population: &[Agent],
+    female: usize,
+    males: &[usize],
+    choice: Option<&MateChoice>,
+    rng: &mut impl Rng,
+) -> Option<usize> {
+    match choice {
+        Some(choice) => choice
+            .choose(&population[female], males, population, rng)
+            .map(|position| males[position]),
+        None => Some(males[rng.gen_range(0..males.len())]),
+    }
+}
+
+/// Pairs in which each agent has at most one mate: in order without mate
+/// choice, otherwise each female in turn chooses among the males left.
+fn pair_up(
+    population: &[Agent],
+    males: &[usize],
+    females: &[usize],
+    choice: Option<&MateChoice>,
+    rng: &mut impl Rng,
+) -> Vec<(usize, usize)> {
+    let Some(choice) = choice else {
+        return males.iter().copied().zip(females.iter().copied()).collect();
+    };
+    let mut available = males.to_vec();
+    let mut couples = Vec::new();
+    for &female in females {
+        if available.is_empty() {
+            break;
+        }
+        if let Some(position) = choice.choose(&population[female], &available, population, rng) {
+            couples.push((available.swap_remove(position), female));
+        }
+    }
+    couples
+}
+
+/// Keeps the pairs whose partners are both alive and pairs the singles.
+fn monogamous_couples(
+    population: &mut [Agent],
+    males: &[usize],
+    females: &[usize],
+    choice: Option<&MateChoice>,
+    rng: &mut impl Rng,
+) -> Vec<(usize, usize)> {
+    let indexes: HashMap<u64, usize> = population.iter().enumerate().map(|(index, agent)| (agent.id, index)).collect();
+    // The partners of the agents who died are single again.
+    for agent in population.iter_mut() {
+        if agent.partner.is_some_and(|partner| !indexes.contains_key(&partner)) {
+            agent.partner = None;
+        }
+    }
+
+    let mut couples: Vec<(usize, usize)> = males
+        .iter()
+        .filter_map(|&male| population[male].partner.map(|partner| (male, indexes[&partner])))
+        .collect();
+    let single_males: Vec<usize> = males.iter().copied().filter(|&male| population[male].partner.is_none()).collect();
+    let single_females: Vec<usize> =
+        females.iter().copied().filter(|&female| population[female].partner.is_none()).collect();
+    let new_couples = pair_up(population, &single_males, &single_females, choice, rng);
+    for &(male, female) in &new_couples {
+        population[male].partner = Some(population[female].id);
+        population[female].partner = Some(population[male].id);
+    }
+    couples.extend(new_couples);
+    couples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gla_package::rng::replicate_rng;
+
+    fn agent(female: bool, ornament: f64) -> Agent {
+        Agent {
+            id: 0,
+            mother: None,
+            father: None,
+            partner: None,
+            birth_time: 0.0,
+            age: 20.0,
+            female,
+            hazard_parameters: Vec::new(),
+            female_fertility_parameters: Vec::new(),
+            male_fertility_parameters: Vec::new(),
+            neutral_parameters: vec![ornament],
+            sex_ratio: 0.5,
+            genome: Vec::new(),
+            age_specific_mutations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn extreme_preferences_still_choose_a_mate() {
+        let ornament = TraitLocation::Neutral { index: 0 };
+        let population = vec![agent(true, 0.0), agent(false, 140.0), agent(false, 150.0), agent(false, 145.0)];
+        let males = [1, 2, 3];
+        let mut rng = replicate_rng(1, 0);
+
+        // exp(5 * 150) overflows: the most ornamented male wins every time.
+        let display = MateChoice {
+            candidates: 3,
+            age_difference: None,
+            assortment: None,
+            display: Some(TraitPreference { location: ornament, strength: 5.0 }),
+        };
+        for _ in 0..100 {
+            assert_eq!(display.choose(&population[0], &males, &population, &mut rng), Some(1));
+        }
+
+        // exp(-1e6 d^2) underflows for every male: the least dissimilar one wins.
+        let assortment = MateChoice {
+            candidates: 3,
+            age_difference: None,
+            assortment: Some(TraitPreference { location: ornament, strength: 1e6 }),
+            display: None,
+        };
+        for _ in 0..100 {
+            assert_eq!(assortment.choose(&population[0], &males, &population, &mut rng), Some(0));
+        }
+    }
+}
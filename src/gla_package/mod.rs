@@ -1,3 +1,16 @@
 pub mod gla;
+pub mod hazard;
+pub mod heritable;
+pub mod age_specific;
 pub mod agent_based;
-pub mod simulate;
\ No newline at end of file
+pub mod checkpoint;
+pub mod config;
+pub mod density;
+pub mod environment;
+pub mod genealogy;
+pub mod litter;
+pub mod mating;
+pub mod rng;
+pub mod simulate;
+pub mod simulation;
+pub mod tradeoff;
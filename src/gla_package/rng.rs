@@ -0,0 +1,26 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+pub type SimulationRng = ChaCha8Rng;
+
+/// Stream of a replicate, fully determined by the master seed and the replicate id.
+pub fn replicate_rng(seed: u64, replicate_id: u64) -> SimulationRng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(replicate_id);
+    rng
+}
+
+/// Independent substream for one unit of parallel work (e.g. one agent during a step).
+///
+/// The substream only depends on `step_seed` and `index`, so results do not
+/// change with the number of rayon threads or the order work is scheduled in.
+///
+/// Neither these streams nor the replicate's are drawn from by a feature that
+/// is disabled or has nothing random to do (no noise, a zero rate, fixed
+/// litters), so that adding a feature leaves the results of runs without it
+/// unchanged.
+pub fn substream(step_seed: u64, index: u64) -> SimulationRng {
+    let mut rng = ChaCha8Rng::seed_from_u64(step_seed);
+    rng.set_stream(index);
+    rng
+}
@@ -1,67 +1,268 @@
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
 use csv::Writer;
 use indicatif::{ProgressBar, ProgressStyle};
+use rayon::prelude::*;
 
-use crate::gla_package::agent_based::{
-    get_death_population, get_population_b_stats, get_population_lmax_stats, get_population_gmax_stats, get_reproduction_population,
-    increment_age_population, initialize_population,
-};
+use crate::gla_package::agent_based::{get_population_trait_stats, TraitStats};
+use crate::gla_package::checkpoint::{completed_replicates, Checkpoint, CheckpointStore};
+use crate::gla_package::config::SimulationConfig;
+use crate::gla_package::density::DensityStats;
+use crate::gla_package::environment::EnvironmentStats;
+use crate::gla_package::genealogy::{LineageStats, PedigreeLog};
+use crate::gla_package::mating::MatingCorrelations;
+use crate::gla_package::simulation::{Simulation, SimulationBuilder};
 
-#[derive(serde::Serialize)]
-struct SimulationResult {
-    mean_b: f64,
-    mean_lmax: f64,
-    mean_gmax: f64,
+/// One row of the results file.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SimulationResult {
     time: f64,
     replicate_id: i32,
+    population_size: usize,
+    females: usize,
+    males: usize,
+    births: usize,
+    deaths: usize,
+    /// One entry per heritable trait, in declaration order.
+    traits: Vec<TraitStats>,
+    /// Mean number of age-specific mutations per agent, when they are enabled.
+    age_specific_mutations: Option<f64>,
+    /// Founder lineages and TMRCA, when lineages are tracked.
+    lineages: Option<LineageStats>,
+    /// Regulation felt by the population, when density regulates it.
+    density: Option<DensityStats>,
+    /// Environmental factor and catastrophe deaths, when the environment varies.
+    environment: Option<EnvironmentStats>,
+    /// Correlations between mates, under mate choice.
+    mating_correlations: Option<MatingCorrelations>,
 }
 
-pub fn run_simulation(
-    output_writer: &mut Writer<File>,
-    population_cap: usize,
-    simulation_time:usize,
-    replicate_id: i32,
-    assortative_mating: bool,
-    aging_parameters: &[f64],
-    learning_parameters: &[f64],
-    growth_parameters: &[f64],
-    initial_age_distribution: [f64; 2],
-    initial_b_distribution: [f64; 2],
-    initial_lmax_distribution: [f64; 2],
-    initial_gmax_distribution: [f64; 2],
-    initial_female_proportion: f64,
-    time_step: f64,
-    mutable_b: bool,
-    mutable_lmax: bool,
-    mutable_gmax: bool,
-    b_mutation_rate: f64,
-    lmax_mutation_rate: f64,
-    gmax_mutation_rate: f64,
-    b_mutation_strength: f64,
-    lmax_mutation_strength: f64,
-    gmax_mutation_strength: f64,
-    aging_intermediate_closure: impl Fn(f64, &[f64], &[f64], &[f64]) -> f64 + Send + Sync,
-    normalized_male_fertility_closure: &Box<impl Fn(f64) -> f64>, 
-    normalized_female_fertility_closure: &Box<impl Fn(f64) -> f64>,
-    tradeoff:bool,
-    start_b: f64,
-    remove_non_reproducing: bool,
-    male_menopause: f64,
-    female_menopause: f64,
-) {
-    // let mut wtr = Writer::from_path("foo.csv").unwrap();
-    let mut population = initialize_population(
-        population_cap,
-        aging_parameters,
-        learning_parameters,
-        growth_parameters,
-        initial_age_distribution,
-        initial_b_distribution,
-        initial_lmax_distribution,
-        initial_gmax_distribution,
-        initial_female_proportion,
-    );
-    let bar = ProgressBar::new(simulation_time as u64);
+impl SimulationResult {
+    /// Summary of the population after a step that started at `time`.
+    pub fn from_simulation(simulation: &Simulation, time: f64) -> Self {
+        let population = simulation.population();
+        let females = population.iter().filter(|agent| agent.female).count();
+
+        SimulationResult {
+            time,
+            replicate_id: simulation.replicate_id() as i32,
+            population_size: population.len(),
+            females,
+            males: population.len() - females,
+            births: simulation.last_births(),
+            deaths: simulation.last_deaths(),
+            traits: simulation
+                .traits()
+                .iter()
+                .map(|heritable_trait| get_population_trait_stats(population, heritable_trait.location))
+                .collect(),
+            age_specific_mutations: simulation.reproduction_parameters().age_specific_mutations.as_ref().map(|_| {
+                population.iter().map(|agent| agent.age_specific_mutations.len()).sum::<usize>() as f64
+                    / population.len() as f64
+            }),
+            lineages: simulation.last_lineages(),
+            density: simulation
+                .reproduction_parameters()
+                .density_regulation
+                .as_ref()
+                .map(|regulation| regulation.stats(population)),
+            environment: simulation.last_environment(),
+            mating_correlations: simulation.last_mating_correlations().cloned(),
+        }
+    }
+
+    /// Column names: the trait means, the population columns, the dispersion
+    /// of each trait, then the columns of the optional subsystems.
+    pub fn headers(config: &SimulationConfig) -> Vec<String> {
+        let traits = config.heritable_traits();
+        let trait_names = traits.iter().map(|heritable_trait| heritable_trait.name.as_str()).collect::<Vec<_>>();
+        let mut headers: Vec<String> = trait_names.iter().map(|name| format!("mean_{}", name)).collect();
+        headers.extend(
+            ["time", "replicate_id", "population_size", "females", "males", "births", "deaths"]
+                .iter()
+                .map(|column| column.to_string()),
+        );
+        for name in trait_names {
+            for statistic in ["variance", "min", "max", "q05", "q25", "median", "q75", "q95"] {
+                headers.push(format!("{}_{}", statistic, name));
+            }
+        }
+        if config.age_specific_mutations.is_some() {
+            headers.push("mean_age_specific_mutations".to_string());
+        }
+        if config.genealogy.track_lineages {
+            headers.extend(["maternal_founder_lineages".to_string(), "maternal_tmrca".to_string()]);
+        }
+        if config.density_regulation.is_some() {
+            headers.extend(
+                ["mean_density", "mean_density_fertility_factor", "mean_density_hazard"].map(String::from),
+            );
+        }
+        if config.environment.is_some() {
+            headers.extend(["environment_factor", "catastrophe_deaths"].map(String::from));
+        }
+        if config.mating.choice.is_some() {
+            headers.push("mating_correlation_age".to_string());
+            for heritable_trait in &traits {
+                headers.push(format!("mating_correlation_{}", heritable_trait.name));
+            }
+        }
+        headers
+    }
+
+    pub fn record(&self) -> Vec<ResultValue> {
+        let mut record: Vec<ResultValue> = self.traits.iter().map(|stats| ResultValue::Float(stats.mean)).collect();
+        record.push(ResultValue::Float(self.time));
+        record.extend(
+            [
+                self.replicate_id as i64,
+                self.population_size as i64,
+                self.females as i64,
+                self.males as i64,
+                self.births as i64,
+                self.deaths as i64,
+            ]
+            .map(ResultValue::Integer),
+        );
+        for stats in &self.traits {
+            record.extend(
+                [
+                    stats.variance,
+                    stats.min,
+                    stats.max,
+                    stats.q05,
+                    stats.q25,
+                    stats.median,
+                    stats.q75,
+                    stats.q95,
+                ]
+                .map(ResultValue::Float),
+            );
+        }
+        record.extend(self.age_specific_mutations.map(ResultValue::Float));
+        if let Some(lineages) = self.lineages {
+            record.push(ResultValue::Integer(lineages.maternal_founder_lineages as i64));
+            record.push(ResultValue::Float(lineages.maternal_tmrca.unwrap_or(f64::NAN)));
+        }
+        if let Some(density) = self.density {
+            record.extend([density.density, density.fertility_factor, density.hazard].map(ResultValue::Float));
+        }
+        if let Some(environment) = self.environment {
+            record.push(ResultValue::Float(environment.factor));
+            record.push(ResultValue::Integer(environment.catastrophe_deaths as i64));
+        }
+        if let Some(correlations) = &self.mating_correlations {
+            record.extend(
+                std::iter::once(correlations.age)
+                    .chain(correlations.traits.iter().copied())
+                    .map(|correlation| ResultValue::Float(correlation.unwrap_or(f64::NAN))),
+            );
+        }
+        record
+    }
+}
+
+/// One cell of a results row, written by the csv serializer so that numbers
+/// are formatted as for a struct field.
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+pub enum ResultValue {
+    Float(f64),
+    Integer(i64),
+}
+
+/// Collects finished replicates and writes them in replicate order, whatever
+/// order they complete in.
+pub struct OrderedResultWriter<W: Write> {
+    state: Mutex<OrderedState<W>>,
+}
+
+struct OrderedState<W: Write> {
+    writer: Writer<W>,
+    next_replicate: usize,
+    pending: BTreeMap<usize, Vec<SimulationResult>>,
+}
+
+impl<W: Write> OrderedResultWriter<W> {
+    pub fn new(writer: Writer<W>, first_replicate: usize) -> Self {
+        OrderedResultWriter {
+            state: Mutex::new(OrderedState {
+                writer,
+                next_replicate: first_replicate,
+                pending: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Queues the results of a replicate and returns the replicates whose rows
+    /// this wrote and flushed, in order.
+    pub fn submit(&self, replicate_id: usize, results: Vec<SimulationResult>) -> csv::Result<Vec<usize>> {
+        let mut state = self.state.lock().unwrap();
+        let mut written = Vec::new();
+        state.pending.insert(replicate_id, results);
+        loop {
+            let next_replicate = state.next_replicate;
+            let Some(results) = state.pending.remove(&next_replicate) else {
+                break;
+            };
+            for res in results {
+                state.writer.serialize(res.record())?;
+            }
+            state.writer.flush()?;
+            written.push(next_replicate);
+            state.next_replicate += 1;
+        }
+        Ok(written)
+    }
+}
+
+/// Runs every replicate of the configuration on a rayon pool of
+/// `replicate_threads` threads and streams the results to the configured
+/// output file.
+///
+/// With `resume`, replicates already complete in the output file are kept,
+/// interrupted ones continue from their last checkpoint and new rows are
+/// appended to the same file.
+pub fn run_replicates(config: &SimulationConfig, resume: bool) -> io::Result<()> {
+    let output_file_path = config.output_file_path();
+    if let Some(parent) = Path::new(&output_file_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let checkpoints = CheckpointStore::new(config.checkpoint_directory(), config.checkpoint_interval);
+    if config.genealogy.pedigree_log {
+        fs::create_dir_all(config.pedigree_directory())?;
+    }
+
+    let first_replicate = if resume {
+        completed_replicates(&output_file_path, config.number_of_steps())?
+    } else {
+        0
+    };
+    let output_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .truncate(false)
+        .open(&output_file_path)?;
+    if !resume {
+        output_file.set_len(0)?;
+    }
+    let has_rows = output_file.metadata()?.len() > 0;
+    let mut output_writer = Writer::from_writer(output_file);
+    if !has_rows {
+        output_writer.write_record(SimulationResult::headers(config))?;
+        output_writer.flush()?;
+    }
+
+    let mut pool_builder = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = config.replicate_threads {
+        pool_builder = pool_builder.num_threads(threads);
+    }
+    let pool = pool_builder.build().unwrap();
+
+    let bar = ProgressBar::new((config.replicate_number * config.number_of_steps()) as u64);
     bar.set_style(
         ProgressStyle::with_template(
             "[{elapsed_precise}] {bar:50.cyan/blue} {pos:>7}/{len:7} {msg}",
@@ -69,40 +270,190 @@ pub fn run_simulation(
         .unwrap()
         .progress_chars("##-"),
     );
-    for i in 0..simulation_time {
-        get_death_population(&mut population, time_step, &aging_intermediate_closure, remove_non_reproducing, male_menopause, female_menopause);
-        get_reproduction_population(
-            &mut population,
-            assortative_mating,
-            &normalized_male_fertility_closure,
-            &normalized_female_fertility_closure,
-            tradeoff,
-            start_b,
-            population_cap,
-            mutable_b,
-            mutable_lmax,
-            mutable_gmax,
-            b_mutation_rate,
-            lmax_mutation_rate,
-            gmax_mutation_rate,
-            b_mutation_strength,
-            lmax_mutation_strength,
-            gmax_mutation_strength,
-        );
-        increment_age_population(&mut population, time_step);
-        let b_stats = get_population_b_stats(&population);
-        let lmax_stats = get_population_lmax_stats(&population);
-        let gmax_stats = get_population_gmax_stats(&population);
-
-        let res = SimulationResult {
-            mean_b: b_stats.0,
-            mean_lmax: lmax_stats.0,
-            mean_gmax: gmax_stats.0,
-            time: (i as f64) * time_step,
-            replicate_id: replicate_id,
-        };
-        let _ = output_writer.serialize(res);
-        bar.inc(1);
+    bar.set_position((first_replicate * config.number_of_steps()) as u64);
+    if first_replicate > 0 {
+        bar.suspend(|| println!("Resuming after {} completed replicates", first_replicate));
     }
+
+    let ordered_writer = OrderedResultWriter::new(output_writer, first_replicate);
+    pool.install(|| {
+        (first_replicate..config.replicate_number).into_par_iter().try_for_each(|i| {
+            let results = run_simulation(config, i, &checkpoints, &bar)?;
+            // A checkpoint goes only once the rows of its replicate are on disk.
+            for written in ordered_writer.submit(i, results)? {
+                checkpoints.remove(written)?;
+            }
+            bar.suspend(|| println!("Replicate : {}/{} done", i + 1, config.replicate_number));
+            Ok::<(), io::Error>(())
+        })
+    })?;
     bar.finish();
+    Ok(())
+}
+
+/// Runs one replicate to the end, starting from its checkpoint when there is
+/// one and saving a new checkpoint every `checkpoint_interval` steps and once
+/// complete, so that a finished replicate waiting for its turn to be written
+/// is not run again on resume. With a
+/// pedigree log, the agents are appended to the replicate's pedigree file as
+/// they are born.
+pub fn run_simulation(
+    config: &SimulationConfig,
+    replicate_id: usize,
+    checkpoints: &CheckpointStore,
+    progress: &ProgressBar,
+) -> io::Result<Vec<SimulationResult>> {
+    let simulation_time = config.number_of_steps();
+    let pedigree_path = Path::new(&config.pedigree_directory()).join(format!("replicate_{}.csv", replicate_id));
+    let (mut simulation, mut results, mut pedigree) = match checkpoints.load(replicate_id)? {
+        Some(checkpoint) => {
+            let pedigree = match (config.genealogy.pedigree_log, checkpoint.pedigree_length) {
+                (true, Some(length)) => Some(PedigreeLog::resume(&pedigree_path, length)?),
+                (true, None) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "checkpoint was saved without a pedigree log",
+                    ))
+                }
+                (false, _) => None,
+            };
+            (
+                SimulationBuilder::from_config(config)
+                    .state(checkpoint.state)
+                    .build(),
+                checkpoint.results,
+                pedigree,
+            )
+        }
+        None => {
+            let simulation = Simulation::from_config(config, replicate_id);
+            let pedigree = if config.genealogy.pedigree_log {
+                let mut pedigree = PedigreeLog::create(&pedigree_path)?;
+                pedigree.record(simulation.population())?;
+                Some(pedigree)
+            } else {
+                None
+            };
+            (simulation, Vec::with_capacity(simulation_time), pedigree)
+        }
+    };
+    progress.inc(simulation.steps() as u64);
+
+    while simulation.steps() < simulation_time {
+        let time = simulation.time();
+        simulation.step();
+        results.push(SimulationResult::from_simulation(&simulation, time));
+        if let Some(pedigree) = &mut pedigree {
+            pedigree.record(simulation.last_born())?;
+        }
+        progress.inc(1);
+
+        let complete = simulation.steps() == simulation_time;
+        if checkpoints.is_due(simulation.steps()) || (complete && checkpoints.is_enabled()) {
+            let checkpoint = Checkpoint {
+                state: simulation.state(),
+                results,
+                pedigree_length: pedigree.as_mut().map(PedigreeLog::flush).transpose()?,
+            };
+            checkpoints.save(&checkpoint)?;
+            results = checkpoint.results;
+        }
+    }
+    if let Some(pedigree) = &mut pedigree {
+        pedigree.flush()?;
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_config() -> SimulationConfig {
+        let config: SimulationConfig = toml::from_str(
+            r#"
+            simulation_time = 15.0
+            time_step = 1.0
+            population_cap = 300
+            initial_population = 300
+            initial_female_proportion = 0.5
+            replicate_number = 2
+            initial_age_distribution = [20.0, 10.0]
+            assortative_mating = false
+            remove_non_reproducing = true
+            minimum_mortality = 1e-5
+            aging_parameters = [0.00275961297460256, 0.04326224872667336, 0.025201676835511704]
+            learning_parameters = [0.01606792505529796, 39.006865144958745, 0.11060749334680318]
+            growth_parameters = [0.05168141300917714, 0.08765165352033985]
+            female_fertility_function = "brass_polynomial"
+            female_fertility_parameters = [2.445e-5, 14.8, 32.836]
+            male_fertility_function = "brass_polynomial"
+            male_fertility_parameters = [2.445e-5, 14.8, 32.836]
+            output_directory = "unused"
+            base_name = "test"
+            seed = 7
+
+            [[traits]]
+            name = "b"
+            component = "aging"
+            parameter = "b"
+            initial_distribution = [0.14, 0.005]
+            mutation_rate = 0.02
+            mutation_strength = 0.012
+            lower_bound = 0.0
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    fn rows(results: &[SimulationResult]) -> String {
+        serde_json::to_string(results).unwrap()
+    }
+
+    #[test]
+    fn resumed_replicate_matches_an_uninterrupted_run() {
+        let config = small_config();
+        let directory = std::env::temp_dir().join(format!("resumed_replicate_{}", std::process::id()));
+        let uninterrupted = run_simulation(&config, 1, &CheckpointStore::new(&directory, None), &ProgressBar::hidden())
+            .unwrap();
+
+        // Stop after 7 steps, as a preempted run would, keeping the checkpoint.
+        let checkpoints = CheckpointStore::new(&directory, Some(7));
+        let mut simulation = Simulation::from_config(&config, 1);
+        let mut results = Vec::new();
+        while simulation.steps() < 7 {
+            let time = simulation.time();
+            simulation.step();
+            results.push(SimulationResult::from_simulation(&simulation, time));
+        }
+        checkpoints
+            .save(&Checkpoint { state: simulation.state(), results, pedigree_length: None })
+            .unwrap();
+
+        let resumed = run_simulation(&config, 1, &checkpoints, &ProgressBar::hidden()).unwrap();
+        assert_eq!(resumed.len(), config.number_of_steps());
+        assert_eq!(rows(&resumed), rows(&uninterrupted));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn replicate_does_not_depend_on_the_number_of_threads() {
+        let config = small_config();
+        let run = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                let mut simulation = Simulation::from_config(&config, 1);
+                let mut results = Vec::new();
+                while simulation.steps() < config.number_of_steps() {
+                    let time = simulation.time();
+                    simulation.step();
+                    results.push(SimulationResult::from_simulation(&simulation, time));
+                }
+                (serde_json::to_string(&simulation.state()).unwrap(), rows(&results))
+            })
+        };
+        assert_eq!(run(1), run(4));
+    }
 }
@@ -0,0 +1,458 @@
+use crate::gla_package::agent_based::{
+    get_death_population, get_reproduction_population, increment_age_population,
+    initialize_population, Agent, DeathParameters,
+    InitialPopulationParameters, ReproductionParameters,
+};
+use crate::gla_package::config::SimulationConfig;
+use crate::gla_package::environment::{Environment, EnvironmentStats};
+use crate::gla_package::genealogy::{Genealogy, LineageStats};
+use crate::gla_package::gla::{find_maximum_fertility, FertilityError};
+use crate::gla_package::hazard::HazardModel;
+use crate::gla_package::heritable::HeritableTrait;
+use crate::gla_package::mating::MatingCorrelations;
+use crate::gla_package::tradeoff::TradeoffTarget;
+use crate::gla_package::rng::{replicate_rng, SimulationRng};
+
+/// Probability of reproducing during a step, given the age and the fertility
+/// parameters of an agent.
+pub type FertilityClosure = dyn Fn(f64, &[f64]) -> f64 + Send + Sync;
+
+/// Fertility schedule rescaled by the maximum of the baseline schedule so that
+/// it can be used as a probability. Agents whose evolved parameters raise the
+/// schedule above that maximum are capped at 1.
+pub fn normalized_fertility(
+    fertility_function: fn(f64, &[f64]) -> f64,
+    maximum_fertility: f64,
+) -> Box<FertilityClosure> {
+    Box::new(move |x: f64, fertility_parameters: &[f64]| {
+        (fertility_function(x, fertility_parameters) / maximum_fertility).min(1.0)
+    })
+}
+
+/// Rescales a user-defined schedule like the built-in families, with the
+/// maximum over `support` of the baseline schedule found numerically since
+/// there is no closed form for it.
+pub fn normalized_custom_fertility(
+    fertility_function: impl Fn(f64, &[f64]) -> f64 + Send + Sync + 'static,
+    baseline_parameters: &[f64],
+    support: (f64, f64),
+) -> Result<Box<FertilityClosure>, FertilityError> {
+    let maximum_fertility = find_maximum_fertility(&fertility_function, baseline_parameters, support)?.value;
+    Ok(Box::new(move |x: f64, fertility_parameters: &[f64]| {
+        (fertility_function(x, fertility_parameters) / maximum_fertility).min(1.0)
+    }))
+}
+
+/// Configures a [`Simulation`]. Start from a configuration with
+/// [`SimulationBuilder::from_config`] and override what needs to differ.
+pub struct SimulationBuilder {
+    initial_population: InitialPopulationParameters,
+    death: DeathParameters,
+    reproduction: ReproductionParameters,
+    hazard_model: HazardModel,
+    traits: Vec<HeritableTrait>,
+    male_fertility: Box<FertilityClosure>,
+    female_fertility: Box<FertilityClosure>,
+    environment: Option<Environment>,
+    track_lineages: bool,
+    population: Option<Vec<Agent>>,
+    state: Option<SimulationState>,
+    seed: u64,
+    replicate_id: usize,
+}
+
+/// Everything that changes while a simulation runs, enough to resume it
+/// exactly where it stopped.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct SimulationState {
+    pub replicate_id: usize,
+    pub steps: usize,
+    pub rng: SimulationRng,
+    pub population: Vec<Agent>,
+    /// Id of the next agent born.
+    pub next_id: u64,
+    pub genealogy: Option<Genealogy>,
+    /// Current value of the environment's AR(1) noise.
+    pub environmental_noise: f64,
+}
+
+impl SimulationBuilder {
+    /// Builder for the configured model. A configuration without a seed gets
+    /// a master seed drawn here, as at startup.
+    pub fn from_config(config: &SimulationConfig) -> Self {
+        SimulationBuilder {
+            initial_population: InitialPopulationParameters {
+                size: config.initial_population,
+                female_proportion: config.initial_female_proportion,
+                age_distribution: config.initial_age_distribution,
+                female_fertility_parameters: config.female_fertility_parameters.clone(),
+                male_fertility_parameters: config.male_fertility_parameters.clone(),
+                neutral_parameters: vec![0.0; config.neutral_parameter_names().len()],
+                sex_ratio: config.litter.sex_ratio,
+            },
+            death: DeathParameters {
+                time_step: config.time_step,
+                death_probability: config.death_probability,
+                remove_non_reproducing: config.remove_non_reproducing,
+                female_fertility_function: config.female_fertility_function,
+                male_fertility_function: config.male_fertility_function,
+                survival_tradeoffs: config.tradeoffs(TradeoffTarget::Survival),
+                density_regulation: config.density_regulation.clone(),
+            },
+            reproduction: ReproductionParameters {
+                assortative_mating: config.assortative_mating,
+                fertility_tradeoffs: config.tradeoffs(TradeoffTarget::Fertility),
+                population_cap: config.population_cap,
+                age_specific_mutations: config.age_specific_mutations.clone(),
+                offspring: config.offspring_template(),
+                density_regulation: config.density_regulation.clone(),
+                mating: config.mating.clone(),
+                mate_choice: config.mate_choice(),
+                litter: config.litter.clone(),
+            },
+            hazard_model: config.hazard_model(),
+            traits: config.heritable_traits(),
+            male_fertility: normalized_fertility(
+                config.male_fertility_function.function(),
+                config.male_maximum_fertility().value,
+            ),
+            female_fertility: normalized_fertility(
+                config.female_fertility_function.function(),
+                config.female_maximum_fertility().value,
+            ),
+            environment: config.environment(),
+            track_lineages: config.genealogy.track_lineages,
+            population: None,
+            state: None,
+            seed: config.seed.unwrap_or_else(rand::random),
+            replicate_id: 0,
+        }
+    }
+
+    pub fn replicate_id(mut self, replicate_id: usize) -> Self {
+        self.replicate_id = replicate_id;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn initial_population(mut self, parameters: InitialPopulationParameters) -> Self {
+        self.initial_population = parameters;
+        self
+    }
+
+    /// Starts from the given agents instead of drawing founders.
+    pub fn population(mut self, population: Vec<Agent>) -> Self {
+        self.population = Some(population);
+        self
+    }
+
+    /// Resumes from a saved state, which takes precedence over the seed,
+    /// replicate id and initial population.
+    pub fn state(mut self, state: SimulationState) -> Self {
+        self.state = Some(state);
+        self
+    }
+
+    pub fn death_parameters(mut self, parameters: DeathParameters) -> Self {
+        self.death = parameters;
+        self
+    }
+
+    pub fn reproduction_parameters(mut self, parameters: ReproductionParameters) -> Self {
+        self.reproduction = parameters;
+        self
+    }
+
+    /// Hazard model of every agent. The offspring template of the
+    /// reproduction parameters must have the same parameters.
+    pub fn hazard_model(mut self, hazard_model: HazardModel) -> Self {
+        self.hazard_model = hazard_model;
+        self
+    }
+
+    /// Parameters that vary between agents and evolve. Their locations must
+    /// match the hazard model and fertility parameters.
+    pub fn traits(mut self, traits: Vec<HeritableTrait>) -> Self {
+        self.traits = traits;
+        self
+    }
+
+    /// Probability for a male of a given age and fertility parameters to
+    /// reproduce during a step; [`normalized_custom_fertility`] turns a raw
+    /// schedule into one.
+    pub fn male_fertility(mut self, fertility: impl Fn(f64, &[f64]) -> f64 + Send + Sync + 'static) -> Self {
+        self.male_fertility = Box::new(fertility);
+        self
+    }
+
+    /// Probability for a female of a given age and fertility parameters to
+    /// reproduce during a step; [`normalized_custom_fertility`] turns a raw
+    /// schedule into one.
+    pub fn female_fertility(mut self, fertility: impl Fn(f64, &[f64]) -> f64 + Send + Sync + 'static) -> Self {
+        self.female_fertility = Box::new(fertility);
+        self
+    }
+
+    /// Seasons, noise and catastrophes acting on a hazard parameter.
+    pub fn environment(mut self, environment: Option<Environment>) -> Self {
+        self.environment = environment;
+        self
+    }
+
+    /// Follows the maternal lineages of the population, see [`Genealogy`].
+    pub fn track_lineages(mut self, track_lineages: bool) -> Self {
+        self.track_lineages = track_lineages;
+        self
+    }
+
+    pub fn build(self) -> Simulation {
+        if let Some(state) = self.state {
+            return Simulation {
+                population: state.population,
+                rng: state.rng,
+                hazard_model: self.hazard_model,
+                traits: self.traits,
+                male_fertility: self.male_fertility,
+                female_fertility: self.female_fertility,
+                death: self.death,
+                reproduction: self.reproduction,
+                steps: state.steps,
+                replicate_id: state.replicate_id,
+                next_id: state.next_id,
+                genealogy: state.genealogy,
+                environment: self.environment,
+                environmental_noise: state.environmental_noise,
+                last_births: 0,
+                last_deaths: 0,
+                last_lineages: None,
+                last_environment: None,
+                last_mating_correlations: None,
+            };
+        }
+
+        let mut rng = replicate_rng(self.seed, self.replicate_id as u64);
+        let population = match self.population {
+            Some(population) => population,
+            None => initialize_population(
+                &self.initial_population,
+                &self.hazard_model.baseline_parameters(),
+                &self.traits,
+                &mut rng,
+            ),
+        };
+        let next_id = population.iter().map(|agent| agent.id + 1).max().unwrap_or(0);
+        let genealogy = self.track_lineages.then(|| Genealogy::new(&population));
+
+        Simulation {
+            population,
+            rng,
+            hazard_model: self.hazard_model,
+            traits: self.traits,
+            male_fertility: self.male_fertility,
+            female_fertility: self.female_fertility,
+            death: self.death,
+            reproduction: self.reproduction,
+            steps: 0,
+            replicate_id: self.replicate_id,
+            next_id,
+            genealogy,
+            environment: self.environment,
+            environmental_noise: 0.0,
+            last_births: 0,
+            last_deaths: 0,
+            last_lineages: None,
+            last_environment: None,
+            last_mating_correlations: None,
+        }
+    }
+}
+
+/// One replicate of the agent based model: a population, its random stream
+/// and the model functions acting on it.
+pub struct Simulation {
+    population: Vec<Agent>,
+    rng: SimulationRng,
+    hazard_model: HazardModel,
+    traits: Vec<HeritableTrait>,
+    male_fertility: Box<FertilityClosure>,
+    female_fertility: Box<FertilityClosure>,
+    death: DeathParameters,
+    reproduction: ReproductionParameters,
+    steps: usize,
+    replicate_id: usize,
+    next_id: u64,
+    genealogy: Option<Genealogy>,
+    environment: Option<Environment>,
+    environmental_noise: f64,
+    last_births: usize,
+    last_deaths: usize,
+    last_lineages: Option<LineageStats>,
+    last_environment: Option<EnvironmentStats>,
+    last_mating_correlations: Option<MatingCorrelations>,
+}
+
+impl Simulation {
+    pub fn from_config(config: &SimulationConfig, replicate_id: usize) -> Self {
+        SimulationBuilder::from_config(config)
+            .replicate_id(replicate_id)
+            .build()
+    }
+
+    /// Advances the population by one time step: deaths, then births, then aging.
+    pub fn step(&mut self) {
+        let time = self.time();
+        let mut catastrophe_deaths = 0;
+        let scaling = match &self.environment {
+            Some(environment) => {
+                self.environmental_noise = environment.next_noise(self.environmental_noise, &mut self.rng);
+                if environment.catastrophe(self.death.time_step, &mut self.rng) {
+                    catastrophe_deaths = environment.kill(&mut self.population, &mut self.rng);
+                }
+                let scaling = environment.scaling(time, self.environmental_noise);
+                self.last_environment = Some(EnvironmentStats {
+                    factor: scaling.factor,
+                    catastrophe_deaths,
+                });
+                Some(scaling)
+            }
+            None => None,
+        };
+        self.last_deaths = catastrophe_deaths
+            + get_death_population(&mut self.population, &self.death, &self.hazard_model, scaling, &mut self.rng);
+        let reproduction = get_reproduction_population(
+            &mut self.population,
+            &self.male_fertility,
+            &self.female_fertility,
+            &self.reproduction,
+            &self.traits,
+            &mut self.rng,
+        );
+        self.last_births = reproduction.births;
+        self.last_mating_correlations = reproduction.mating_correlations;
+        let first_born = self.population.len() - self.last_births;
+        for agent in &mut self.population[first_born..] {
+            agent.id = self.next_id;
+            agent.birth_time = time;
+            self.next_id += 1;
+        }
+        increment_age_population(&mut self.population, self.death.time_step);
+        self.steps += 1;
+        let now = self.time();
+        if let Some(genealogy) = &mut self.genealogy {
+            genealogy.add(&self.population[first_born..]);
+            self.last_lineages = Some(genealogy.update(&self.population, now));
+        }
+    }
+
+    pub fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    pub fn population(&self) -> &[Agent] {
+        &self.population
+    }
+
+    pub fn population_mut(&mut self) -> &mut Vec<Agent> {
+        &mut self.population
+    }
+
+    /// Number of steps taken so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn time(&self) -> f64 {
+        self.steps as f64 * self.death.time_step
+    }
+
+    pub fn hazard_model(&self) -> &HazardModel {
+        &self.hazard_model
+    }
+
+    pub fn traits(&self) -> &[HeritableTrait] {
+        &self.traits
+    }
+
+    pub fn reproduction_parameters(&self) -> &ReproductionParameters {
+        &self.reproduction
+    }
+
+    /// Births during the last step.
+    pub fn last_births(&self) -> usize {
+        self.last_births
+    }
+
+    /// Deaths during the last step.
+    pub fn last_deaths(&self) -> usize {
+        self.last_deaths
+    }
+
+    /// Agents born during the last step, at the end of the population.
+    pub fn last_born(&self) -> &[Agent] {
+        &self.population[self.population.len() - self.last_births..]
+    }
+
+    /// Environmental factor and catastrophe deaths of the last step, when the
+    /// environment varies.
+    pub fn last_environment(&self) -> Option<EnvironmentStats> {
+        self.last_environment
+    }
+
+    /// Founder lineages and TMRCA after the last step, when lineages are tracked.
+    pub fn last_lineages(&self) -> Option<LineageStats> {
+        self.last_lineages
+    }
+
+    /// Correlations between mates during the last step, under mate choice.
+    pub fn last_mating_correlations(&self) -> Option<&MatingCorrelations> {
+        self.last_mating_correlations.as_ref()
+    }
+
+    pub fn time_step(&self) -> f64 {
+        self.death.time_step
+    }
+
+    pub fn replicate_id(&self) -> usize {
+        self.replicate_id
+    }
+
+    pub fn rng_mut(&mut self) -> &mut SimulationRng {
+        &mut self.rng
+    }
+
+    pub fn state(&self) -> SimulationState {
+        SimulationState {
+            replicate_id: self.replicate_id,
+            steps: self.steps,
+            rng: self.rng.clone(),
+            population: self.population.clone(),
+            next_id: self.next_id,
+            genealogy: self.genealogy.clone(),
+            environmental_noise: self.environmental_noise,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gla_package::config::FertilityFunction;
+
+    #[test]
+    fn custom_fertility_is_normalized_like_the_built_in_families() {
+        let family = FertilityFunction::BrassPolynomial;
+        let parameters = [2.445e-5, 14.8, 32.836];
+        let built_in = normalized_fertility(family.function(), family.maximum(&parameters).unwrap().value);
+        let custom = normalized_custom_fertility(family.function(), &parameters, family.support(&parameters)).unwrap();
+        for age in [10.0, 15.0, 20.0, 25.75, 30.0, 45.0, 60.0] {
+            assert!((built_in(age, &parameters) - custom(age, &parameters)).abs() < 1e-9, "age {}", age);
+        }
+        assert!(normalized_custom_fertility(|_, _| 0.0, &[], (15.0, 50.0)).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use serde::Deserialize;
+
+use crate::gla_package::agent_based::Agent;
+use crate::gla_package::heritable::TraitLocation;
+
+/// What a tradeoff scales.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradeoffTarget {
+    /// The probability of reproducing during a step, capped at 1.
+    Fertility,
+    /// The hazard integrated over a step, so that a factor above 1 shortens
+    /// life while `1 - exp(-H)` keeps the death probability below 1.
+    Survival,
+}
+
+/// Shape of the factor as a function of `r`, the trait value divided by its reference.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TradeoffForm {
+    /// `intercept + slope * r`; the defaults 0 and 1 give the factor `r`.
+    Linear { intercept: f64, slope: f64 },
+    /// `r^exponent`.
+    Power { exponent: f64 },
+    /// `(1 + k) r / (k + r)`: 1 at the reference, levelling off at `1 + k`.
+    Saturating { half_saturation: f64 },
+    /// 1 when the trait value reaches `threshold`, `below` otherwise.
+    Threshold { threshold: f64, below: f64 },
+}
+
+/// Links a heritable trait to fertility or survival. Negative factors are
+/// clamped at 0.
+#[derive(Clone, Debug)]
+pub struct Tradeoff {
+    pub location: TraitLocation,
+    /// Trait value at which the factor is 1, except for thresholds.
+    pub reference: f64,
+    pub target: TradeoffTarget,
+    pub form: TradeoffForm,
+}
+
+impl Tradeoff {
+    pub fn factor(&self, agent: &Agent) -> f64 {
+        let value = agent.trait_value(self.location);
+        let relative = value / self.reference;
+        let factor = match self.form {
+            TradeoffForm::Linear { intercept, slope } => intercept + slope * relative,
+            TradeoffForm::Power { exponent } => relative.powf(exponent),
+            TradeoffForm::Saturating { half_saturation } => {
+                (1.0 + half_saturation) * relative / (half_saturation + relative)
+            }
+            TradeoffForm::Threshold { threshold, below } => {
+                if value >= threshold {
+                    1.0
+                } else {
+                    below
+                }
+            }
+        };
+        factor.max(0.0)
+    }
+}
+
+/// Product of the factors of `tradeoffs`, 1 when there are none.
+pub fn tradeoff_factor(tradeoffs: &[Tradeoff], agent: &Agent) -> f64 {
+    tradeoffs.iter().map(|tradeoff| tradeoff.factor(agent)).product()
+}
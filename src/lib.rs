@@ -0,0 +1 @@
+pub mod gla_package;
@@ -1,9 +1,11 @@
-mod gla_package;
-use csv::Writer;
-use crate::gla_package::{gla::{
-    aging_gompertz_makeham, fertility_brass_polynomial, find_maximum_fertility, gla_model,
-    growth_function, learning_function, constant_fertility,
-}, simulate::run_simulation};
+mod cli;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use clap::Parser;
+use crate::cli::{grid_points, parse_grid_axis, parse_override, Cli, Command, ConfigArgs};
+use agent_based_model::gla_package::{config::SimulationConfig, simulate::run_replicates};
 
 // use easybench::bench;
 
@@ -11,131 +13,137 @@ use crate::gla_package::{gla::{
 // use peroxide::numerical::integral::{gauss_kronrod_quadrature, integrate};
 
 fn main() {
-    let time_step = 1.0;
-    let initial_female_proportion = 0.5;
-    let minimum_mortality = 1e-5;
-    let aging_parameters = [0.00275961297460256,0.04326224872667336,0.025201676835511704] ;
-    let learning_parameters = [0.01606792505529796,39.006865144958745,0.11060749334680318];
-    let growth_parameters: [f64; 2] = [0.05168141300917714,0.08765165352033985];
-
-    // let female_fertility_parameters = [1.0];
-    // let male_fertility_parameters = [1.0];
-
-    // let female_fertility_function = constant_fertility;
-    // let male_fertility_function = constant_fertility;
-
-    let female_fertility_parameters = [2.445e-5, 14.8, 32.836];
-    let male_fertility_parameters = [2.445e-5, 14.8, 32.836];
-
-    let female_menopause = female_fertility_parameters[1] + female_fertility_parameters[2];
-    let male_menopause = male_fertility_parameters[1] + male_fertility_parameters[2];
-
-    // let male_fertility_parameters = [0.00000978, 14.8, 47.836];
-
-    let female_fertility_function = fertility_brass_polynomial;
-    let male_fertility_function = fertility_brass_polynomial;
-
-    let female_maximum_fertility = find_maximum_fertility(
-        &female_fertility_function,
-        &female_fertility_parameters,
-        20.0,
-    );
-    let male_maximum_fertility =
-        find_maximum_fertility(&male_fertility_function, &male_fertility_parameters, 20.0);
-
-    let normalized_male_fertility_closure = Box::new(|x: f64| -> f64 {
-        (male_fertility_function(x, &male_fertility_parameters) / male_maximum_fertility).min(1.0)
-    });
-
-    let normalized_female_fertility_closure = Box::new(|x: f64| -> f64{
-        (female_fertility_function(x, &female_fertility_parameters) / female_maximum_fertility)
-            .min(1.0)
-    });
-
-    let aging_intermediate_closure = |x: f64,
-                                      aging_parameters: &[f64],
-                                      learning_parameters: &[f64],
-                                      growth_parameters: &[f64]|
-     -> f64 {
-        gla_model(
-            x,
-            aging_gompertz_makeham as fn(f64, &[f64]) -> f64,
-            learning_function,
-            growth_function,
-            &aging_parameters,
-            &learning_parameters,
-            &growth_parameters,
-            minimum_mortality,
-        )
-    };
-    // println!("Female maximum fertility : {}", female_maximum_fertility);
-    // println!("Male maximum fertility : {}", male_maximum_fertility);
-
-    let initial_age_distribution = [20.0, 10.0];
-    let initial_b_distribution = [0.14, 0.005];
-    let mut initial_lmax_distribution = [0.125, 0.0];
-    let mut initial_gmax_distribution = [0.05168141300917714, 0.0];
-
-    let population_cap = 10000;
-    let simulation_time : usize = 1000;
-    let replicate_number = 500;
-    let assortative_mating = false;
-    let remove_non_reproducing = true;
-    let tradeoff = false;
-    let start_b = initial_b_distribution[0];
-
-    let mutable_b = true;
-    let mutable_lmax = false;
-    let mutable_gmax = false;
-    let b_mutation_rate: f64 = 0.02;
-    let lmax_mutation_rate: f64 = 0.02;
-    let gmax_mutation_rate: f64 = 0.02;
-    let b_mutation_strength = 0.012;
-    let lmax_mutation_strength = 0.012;
-    let gmax_mutation_strength = 0.012;
-
-    // let base_name_part = "plateau_brass_polynomial_equal_both";
-    let base_name_part = "early_slope_brass_polynomial_equal_both";
-    let mut learning_name_part = "with_learning";
-    let mut mating_name_part = "random_mating";
-    let mut removal_name_part = "non_reproducing_kept";
-    let mut tradeoff_name_part = "no_tradeoff";
-
-    if tradeoff{
-        tradeoff_name_part = "tradeoff";
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Run { config, out, resume } => {
+            let mut simulation_config = load_config(&config, &[]);
+            if let Some(out) = out {
+                simulation_config.output_directory = out.display().to_string();
+            }
+            resolve_seed(&mut simulation_config, resume);
+            run_experiment(&simulation_config, resume);
+        }
+        Command::Sweep { config, out, grid, resume } => {
+            let axes = grid
+                .iter()
+                .map(|axis| parse_grid_axis(axis))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_or_else(|e| exit_with_error(&e));
+            let points = grid_points(&axes);
+            for (i, point) in points.iter().enumerate() {
+                let mut simulation_config = load_config(&config, point);
+                if let Some(out) = &out {
+                    simulation_config.output_directory = out.display().to_string();
+                }
+                simulation_config.base_name = format!("{}_{}", simulation_config.base_name, grid_label(point));
+                println!("Grid point : {}/{} ({})", i + 1, points.len(), grid_label(point));
+                resolve_seed(&mut simulation_config, resume);
+                run_experiment(&simulation_config, resume);
+            }
+        }
+        Command::Inspect { config } => {
+            let simulation_config = load_config(&config, &[]);
+            inspect(&simulation_config);
+        }
     }
-
-    if assortative_mating{
-        mating_name_part = "assortative_mating";
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
+
+fn load_config(args: &ConfigArgs, extra_overrides: &[(String, toml::Value)]) -> SimulationConfig {
+    let mut overrides = args
+        .set
+        .iter()
+        .map(|assignment| parse_override(assignment))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| exit_with_error(&e));
+    overrides.extend_from_slice(extra_overrides);
+
+    SimulationConfig::from_file_with_overrides(&args.config, &overrides)
+        .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", args.config.display(), e)))
+}
+
+/// Draws a master seed when the configuration has none and reports it so the
+/// run can be repeated. The drawn seed is saved next to the results, and a
+/// resumed run reuses it.
+fn resolve_seed(config: &mut SimulationConfig, resume: bool) {
+    if config.seed.is_none() {
+        let seed_file_path = config.seed_file_path();
+        let seed = if resume && Path::new(&seed_file_path).exists() {
+            fs::read_to_string(&seed_file_path)
+                .map_err(|e| e.to_string())
+                .and_then(|seed| seed.trim().parse().map_err(|_| "invalid seed".to_string()))
+                .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", seed_file_path, e)))
+        } else if resume && Path::new(&config.output_file_path()).exists() {
+            exit_with_error(&format!(
+                "{}: cannot resume without the seed of the interrupted run, set `seed` in the configuration",
+                seed_file_path
+            ));
+        } else {
+            let seed = rand::random();
+            if let Some(parent) = Path::new(&seed_file_path).parent() {
+                fs::create_dir_all(parent).unwrap_or_else(|e| exit_with_error(&format!("{}: {}", seed_file_path, e)));
+            }
+            fs::write(&seed_file_path, format!("{}\n", seed))
+                .unwrap_or_else(|e| exit_with_error(&format!("{}: {}", seed_file_path, e)));
+            seed
+        };
+        config.seed = Some(seed);
     }
-
-    if remove_non_reproducing{
-        removal_name_part = "non_reproducing_removed";
+    println!("Seed : {}", config.seed.unwrap());
+}
+
+fn grid_label(point: &[(String, toml::Value)]) -> String {
+    point
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .to_string()
+                .chars()
+                .filter(|c| c.is_alphanumeric() || matches!(c, '.' | '-' | ','))
+                .collect::<String>()
+                .replace(',', "-");
+            format!("{}-{}", key, value)
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+fn inspect(config: &SimulationConfig) {
+    println!("{:#?}", config);
+    println!();
+    println!("Number of steps : {}", config.number_of_steps());
+    println!("Output file : {}", config.output_file_path());
+    for (sex, function, parameters, maximum) in [
+        (
+            "Female",
+            config.female_fertility_function,
+            &config.female_fertility_parameters,
+            config.female_maximum_fertility(),
+        ),
+        (
+            "Male",
+            config.male_fertility_function,
+            &config.male_fertility_parameters,
+            config.male_maximum_fertility(),
+        ),
+    ] {
+        let (start, end) = function.support(parameters);
+        println!("{} reproductive window : [{}, {}]", sex, start, end);
+        println!("{} maximum fertility : {} at age {}", sex, maximum.value, maximum.age);
     }
+}
 
-    println!("######################################");
-    println!("###### Simulation with learning ######");
-    println!("######################################");
-
-    let output_file_name = format!("./simulation_results/{}_{}_{}_{}_{}_{}.csv", base_name_part, mating_name_part, learning_name_part, removal_name_part, tradeoff_name_part,initial_lmax_distribution[0]);
-    let mut wtr = Writer::from_path(output_file_name).unwrap();
+fn run_experiment(config: &SimulationConfig, resume: bool) {
+    let banner = format!("###### Simulation with {} ######", config.hazard_model().names().join(" + "));
+    println!("{}", "#".repeat(banner.len()));
+    println!("{}", banner);
+    println!("{}", "#".repeat(banner.len()));
 
-    for i in 0..replicate_number{
-        println!("Replicate : {}/{}", i+1, replicate_number);
-        run_simulation(&mut wtr, population_cap, simulation_time, i, assortative_mating, &aging_parameters, &learning_parameters, &growth_parameters, initial_age_distribution, initial_b_distribution, initial_lmax_distribution, initial_gmax_distribution, initial_female_proportion, time_step, mutable_b, mutable_lmax, mutable_gmax, b_mutation_rate, lmax_mutation_rate, gmax_mutation_rate, b_mutation_strength, lmax_mutation_strength, gmax_mutation_strength, aging_intermediate_closure, &normalized_male_fertility_closure, &normalized_female_fertility_closure, tradeoff, start_b, remove_non_reproducing, male_menopause, female_menopause)
+    if let Err(e) = run_replicates(config, resume) {
+        exit_with_error(&format!("{}: {}", config.output_file_path(), e));
     }
-
-    // println!("#########################################");
-    // println!("###### Simulation without learning ######");
-    // println!("#########################################");
-    // initial_lmax_distribution = [0.0, 0.0];
-    // learning_name_part = "no_learning";
-
-    // let output_file_name = format!("./simulation_results/{}_{}_{}_{}_{}.csv", base_name_part, mating_name_part, learning_name_part, removal_name_part, tradeoff_name_part);
-    // let mut wtr = Writer::from_path(output_file_name).unwrap();
-
-    // for i in 0..replicate_number{
-    //     println!("Replicate : {}/{}", i+1, replicate_number);
-    //     run_simulation(&mut wtr, population_cap, simulation_time, i, assortative_mating, &aging_parameters, &learning_parameters, &growth_parameters, initial_age_distribution, initial_b_distribution, initial_lmax_distribution, initial_gmax_distribution, initial_female_proportion, time_step, mutable_b, mutable_lmax, mutable_gmax, b_mutation_rate, lmax_mutation_rate, gmax_mutation_rate, b_mutation_strength, lmax_mutation_strength, gmax_mutation_strength, aging_intermediate_closure, &normalized_male_fertility_closure, &normalized_female_fertility_closure, tradeoff, start_b, remove_non_reproducing, male_menopause, female_menopause)
-    // }
-}
\ No newline at end of file
+}